@@ -0,0 +1,824 @@
+use crate::{cpu::CLOCK_FREQUENCY, memory::MemoryIO};
+
+/// Sample rate used until the host asks for another one.
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// The bits of each register in 0xff10-0xff26 that always read as 1. Write-only bits and unused registers read 1s.
+#[rustfmt::skip]
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf, // NR10-NR14
+    0xff, 0x3f, 0x00, 0xff, 0xbf, // NR20-NR24
+    0x7f, 0xff, 0x9f, 0xff, 0xbf, // NR30-NR34
+    0xff, 0xff, 0x00, 0x00, 0xbf, // NR40-NR44
+    0x00, 0x00, 0x70,             // NR50-NR52
+];
+
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Disables the channel after a number of 256 Hz steps, if enabled.
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    fn new(max: u16) -> Self {
+        Self {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    fn load(&mut self, n: u8) {
+        self.counter = self.max - u16::from(n);
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Returns false once the counter has expired, which disables the channel.
+    fn step(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+}
+
+/// Volume envelope of the square and noise channels, stepped at 64 Hz.
+///
+/// - Bit 7-4 - Initial volume
+/// - Bit 3   - Direction (0=Decrease, 1=Increase)
+/// - Bit 2-0 - Sweep pace (0=No sweep)
+struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    volume: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Self {
+            initial: 0,
+            increase: false,
+            period: 0,
+            timer: 0,
+            volume: 0,
+        }
+    }
+
+    fn set(&mut self, n: u8) {
+        self.initial = n >> 4;
+        self.increase = n & 0x08 != 0;
+        self.period = n & 0x07;
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    fn step(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 0x0f {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0x00 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+/// Frequency sweep of channel 1 (NR10), stepped at 128 Hz.
+///
+/// - Bit 6-4 - Sweep pace
+/// - Bit 3   - Direction (0=Addition, 1=Subtraction)
+/// - Bit 2-0 - Individual step
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow: u16,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Self {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            enabled: false,
+            shadow: 0,
+        }
+    }
+
+    fn set(&mut self, n: u8) {
+        self.period = (n >> 4) & 0x07;
+        self.negate = n & 0x08 != 0;
+        self.shift = n & 0x07;
+    }
+
+    fn reload_timer(&mut self) {
+        // A pace of 0 is treated as 8 by the timer.
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    /// The next frequency, which may overflow the 11 bits.
+    fn next_frequency(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.shadow.wrapping_sub(delta)
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+/// Channels 1 and 2. Only channel 1 has a sweep.
+struct Square {
+    enabled: bool,
+    dac_enabled: bool,
+    duty: u8,
+    phase: u8,
+    frequency: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl Square {
+    fn new(sweep: Option<Sweep>) -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            phase: 0,
+            frequency: 0,
+            timer: 8192,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 4
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.phase = (self.phase + 1) & 0x07;
+        }
+        self.timer -= cycles;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.length.trigger();
+        self.envelope.trigger();
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            if sweep.shift != 0 && sweep.next_frequency() > 0x07ff {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn step_sweep(&mut self) {
+        let sweep = match self.sweep.as_mut() {
+            Some(sweep) => sweep,
+            None => return,
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+        let frequency = sweep.next_frequency();
+        if frequency > 0x07ff {
+            self.enabled = false;
+        } else if sweep.shift != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            // The overflow check is performed again with the new frequency.
+            if sweep.next_frequency() > 0x07ff {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && DUTY_PATTERNS[self.duty as usize] & (0x80 >> self.phase) != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// Channel 3, which plays the 32 4-bit samples of the wave RAM (0xff30-0xff3f).
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    length: LengthCounter,
+    ram: [u8; 0x10],
+}
+
+impl Wave {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 4096,
+            position: 0,
+            length: LengthCounter::new(256),
+            ram: [0; 0x10],
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 2
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1f;
+        }
+        self.timer -= cycles;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.position = 0;
+        self.length.trigger();
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let sample = self.ram[self.position as usize >> 1];
+        let sample = if self.position & 0x01 == 0 {
+            sample >> 4
+        } else {
+            sample & 0x0f
+        };
+        match self.volume_code {
+            0x01 => sample,
+            0x02 => sample >> 1,
+            0x03 => sample >> 2,
+            _ => 0,
+        }
+    }
+}
+
+/// Channel 4, pseudo-random noise from a linear feedback shift register.
+///
+/// NR43:
+/// - Bit 7-4 - Clock shift
+/// - Bit 3   - LFSR width (0=15 bits, 1=7 bits)
+/// - Bit 2-0 - Clock divider
+struct Noise {
+    enabled: bool,
+    dac_enabled: bool,
+    shift: u8,
+    short: bool,
+    divisor: u8,
+    lfsr: u16,
+    timer: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            shift: 0,
+            short: false,
+            divisor: 0,
+            lfsr: 0x7fff,
+            timer: 8,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.short {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.lfsr = 0x7fff;
+        self.length.trigger();
+        self.envelope.trigger();
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+/// Audio processing unit
+///
+/// Four channels (two squares, wave and noise) are mixed into a left and a right output by NR51, then scaled by
+/// the master volume of NR50. The length counters, the sweep and the envelopes are stepped by the frame sequencer,
+/// which is itself clocked by DIV at 512 Hz:
+///
+/// | Step | Length | Sweep | Envelope |
+/// |------|--------|-------|----------|
+/// | 0    | Clock  |       |          |
+/// | 2    | Clock  | Clock |          |
+/// | 4    | Clock  |       |          |
+/// | 6    | Clock  | Clock |          |
+/// | 7    |        |       | Clock    |
+///
+/// The output is resampled to the host sample rate and stored as interleaved stereo `f32` samples, to be pulled by
+/// the frontend with `take_samples`.
+pub struct Apu {
+    enabled: bool,
+    /// Raw values of 0xff10-0xff26, mostly kept for reading back.
+    registers: [u8; 0x17],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    frame_sequencer: u8,
+
+    sample_rate: u32,
+    /// Charge kept by the high-pass filter capacitor from one sample to the next, depends on the sample rate.
+    charge: f32,
+    /// Clock cycles times the sample rate, a sample is produced each time it reaches the clock frequency.
+    sample_clock: u64,
+    /// Charge of the high-pass filter capacitor, for the left and right output.
+    capacitor: [f32; 2],
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            enabled: false,
+            registers: [0; 0x17],
+            square1: Square::new(Some(Sweep::new())),
+            square2: Square::new(None),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            frame_sequencer: 0,
+            sample_rate,
+            charge: charge(sample_rate),
+            sample_clock: 0,
+            capacitor: [0.0; 2],
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Can be changed at any time, e.g. slightly every frame for dynamic rate control.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.charge = charge(sample_rate);
+    }
+
+    /// Take the samples produced since the last call, interleaved as left, right, left, right...
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// 往前走若干个时钟周期（普通速度）
+    pub fn tick(&mut self, mut cycles: u32) {
+        let clock = u64::from(CLOCK_FREQUENCY);
+        let rate = u64::from(self.sample_rate).max(1);
+        while cycles > 0 {
+            // Run the channels up to the next sample point, so that long ticks are not sampled at a single instant.
+            let until = (clock - self.sample_clock).div_ceil(rate) as u32;
+            let step = cycles.min(until.max(1));
+            if self.enabled {
+                self.square1.tick(step);
+                self.square2.tick(step);
+                self.wave.tick(step);
+                self.noise.tick(step);
+            }
+            cycles -= step;
+            self.sample_clock += u64::from(step) * rate;
+            if self.sample_clock >= clock {
+                self.sample_clock -= clock;
+                self.push_sample();
+            }
+        }
+    }
+
+    /// Clocked by the falling edge of DIV bit 4 (bit 5 in double speed mode), at 512 Hz.
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.frame_sequencer & 0x01 == 0 {
+            self.square1.enabled &= self.square1.length.step();
+            self.square2.enabled &= self.square2.length.step();
+            self.wave.enabled &= self.wave.length.step();
+            self.noise.enabled &= self.noise.length.step();
+        }
+        if self.frame_sequencer == 2 || self.frame_sequencer == 6 {
+            self.square1.step_sweep();
+        }
+        if self.frame_sequencer == 7 {
+            self.square1.envelope.step();
+            self.square2.envelope.step();
+            self.noise.envelope.step();
+        }
+        self.frame_sequencer = (self.frame_sequencer + 1) & 0x07;
+    }
+
+    /// The digital output of each channel, 0-15. The DAC of a channel turns it into -1.0..1.0, or 0 when disabled.
+    fn dac_outputs(&self) -> [f32; 4] {
+        let dac = |enabled: bool, output: u8| {
+            if enabled {
+                f32::from(output) / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+        [
+            dac(self.square1.dac_enabled, self.square1.output()),
+            dac(self.square2.dac_enabled, self.square2.output()),
+            dac(self.wave.dac_enabled, self.wave.output()),
+            dac(self.noise.dac_enabled, self.noise.output()),
+        ]
+    }
+
+    fn push_sample(&mut self) {
+        // Don't let the buffer grow forever if nobody pulls it, one second is plenty.
+        if self.samples.len() >= self.sample_rate as usize * 2 {
+            return;
+        }
+        let outputs = self.dac_outputs();
+        let nr50 = self.registers[0x14];
+        let nr51 = self.registers[0x15];
+        // Right is the lower nibble of NR51 and bits 2-0 of NR50, left is the upper nibble and bits 6-4.
+        let volumes = [f32::from((nr50 >> 4) & 0x07), f32::from(nr50 & 0x07)];
+        let mut sample = [0.0; 2];
+        for (side, shift) in [(0, 4), (1, 0)] {
+            let mut mix = 0.0;
+            for (i, output) in outputs.iter().enumerate() {
+                if (nr51 >> shift) & (1 << i) != 0 {
+                    mix += output;
+                }
+            }
+            let mix = if self.enabled {
+                mix / 4.0 * (volumes[side] + 1.0) / 8.0
+            } else {
+                0.0
+            };
+            // High-pass filter, removes the DC offset like the capacitor of the real hardware.
+            sample[side] = mix - self.capacitor[side];
+            self.capacitor[side] = mix - sample[side] * self.charge;
+        }
+        self.samples.extend_from_slice(&sample);
+    }
+
+    /// PCM12 and PCM34 (CGB only): the digital outputs of the channels.
+    pub fn pcm(&self, address: u16) -> u8 {
+        match address {
+            0xff76 => self.square1.output() | (self.square2.output() << 4),
+            _ => self.wave.output() | (self.noise.output() << 4),
+        }
+    }
+
+    fn power_off(&mut self) {
+        let sample_rate = self.sample_rate;
+        let samples = std::mem::take(&mut self.samples);
+        let capacitor = self.capacitor;
+        let ram = self.wave.ram;
+        *self = Self::new(sample_rate);
+        self.samples = samples;
+        self.capacitor = capacitor;
+        // The wave RAM is not affected by the power control.
+        self.wave.ram = ram;
+    }
+}
+
+/// The capacitor keeps 0.999958 of its charge every clock cycle.
+fn charge(sample_rate: u32) -> f32 {
+    0.999_958_f32.powf(CLOCK_FREQUENCY as f32 / sample_rate.max(1) as f32)
+}
+
+impl MemoryIO for Apu {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0xff26 => {
+                let mut res = READ_MASKS[0x16];
+                if self.enabled {
+                    res |= 0x80;
+                }
+                for (i, enabled) in [
+                    self.square1.enabled,
+                    self.square2.enabled,
+                    self.wave.enabled,
+                    self.noise.enabled,
+                ]
+                .iter()
+                .enumerate()
+                {
+                    if *enabled {
+                        res |= 1 << i;
+                    }
+                }
+                res
+            }
+            0xff10..=0xff25 => {
+                let i = address as usize - 0xff10;
+                self.registers[i] | READ_MASKS[i]
+            }
+            0xff30..=0xff3f => self.wave.ram[address as usize - 0xff30],
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        if address == 0xff26 {
+            if n & 0x80 == 0 {
+                self.power_off();
+            } else if !self.enabled {
+                self.enabled = true;
+                self.frame_sequencer = 0;
+            }
+            return;
+        }
+        if let 0xff30..=0xff3f = address {
+            self.wave.ram[address as usize - 0xff30] = n;
+            return;
+        }
+        // The registers are read only while the APU is powered off.
+        if !self.enabled || !(0xff10..=0xff25).contains(&address) {
+            return;
+        }
+        self.registers[address as usize - 0xff10] = n;
+        match address {
+            0xff10 => self.square1.sweep.as_mut().unwrap().set(n),
+            0xff11 => {
+                self.square1.duty = n >> 6;
+                self.square1.length.load(n & 0x3f);
+            }
+            0xff12 => {
+                self.square1.envelope.set(n);
+                self.square1.dac_enabled = n & 0xf8 != 0;
+                self.square1.enabled &= self.square1.dac_enabled;
+            }
+            0xff13 => self.square1.frequency = (self.square1.frequency & 0x0700) | u16::from(n),
+            0xff14 => {
+                self.square1.frequency =
+                    (self.square1.frequency & 0x00ff) | (u16::from(n & 0x07) << 8);
+                self.square1.length.enabled = n & 0x40 != 0;
+                if n & 0x80 != 0 {
+                    self.square1.trigger();
+                }
+            }
+            0xff16 => {
+                self.square2.duty = n >> 6;
+                self.square2.length.load(n & 0x3f);
+            }
+            0xff17 => {
+                self.square2.envelope.set(n);
+                self.square2.dac_enabled = n & 0xf8 != 0;
+                self.square2.enabled &= self.square2.dac_enabled;
+            }
+            0xff18 => self.square2.frequency = (self.square2.frequency & 0x0700) | u16::from(n),
+            0xff19 => {
+                self.square2.frequency =
+                    (self.square2.frequency & 0x00ff) | (u16::from(n & 0x07) << 8);
+                self.square2.length.enabled = n & 0x40 != 0;
+                if n & 0x80 != 0 {
+                    self.square2.trigger();
+                }
+            }
+            0xff1a => {
+                self.wave.dac_enabled = n & 0x80 != 0;
+                self.wave.enabled &= self.wave.dac_enabled;
+            }
+            0xff1b => self.wave.length.load(n),
+            0xff1c => self.wave.volume_code = (n >> 5) & 0x03,
+            0xff1d => self.wave.frequency = (self.wave.frequency & 0x0700) | u16::from(n),
+            0xff1e => {
+                self.wave.frequency = (self.wave.frequency & 0x00ff) | (u16::from(n & 0x07) << 8);
+                self.wave.length.enabled = n & 0x40 != 0;
+                if n & 0x80 != 0 {
+                    self.wave.trigger();
+                }
+            }
+            0xff20 => self.noise.length.load(n & 0x3f),
+            0xff21 => {
+                self.noise.envelope.set(n);
+                self.noise.dac_enabled = n & 0xf8 != 0;
+                self.noise.enabled &= self.noise.dac_enabled;
+            }
+            0xff22 => {
+                self.noise.shift = n >> 4;
+                self.noise.short = n & 0x08 != 0;
+                self.noise.divisor = n & 0x07;
+            }
+            0xff23 => {
+                self.noise.length.enabled = n & 0x40 != 0;
+                if n & 0x80 != 0 {
+                    self.noise.trigger();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get16(&self, _: u16) -> u16 {
+        unimplemented!("APU doesn't support reading 2-byte data.")
+    }
+
+    fn set16(&mut self, _: u16, _: u16) {
+        unimplemented!("APU doesn't support writing 2-byte data.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_channel() {
+        let mut apu = Apu::new(DEFAULT_SAMPLE_RATE);
+        apu.set8(0xff26, 0x80);
+        apu.set8(0xff24, 0x77);
+        apu.set8(0xff25, 0x11);
+        // 50% duty, volume 15, 1 kHz-ish.
+        apu.set8(0xff11, 0x80);
+        apu.set8(0xff12, 0xf0);
+        apu.set8(0xff13, 0x83);
+        apu.set8(0xff14, 0x87);
+        assert_eq!(apu.get8(0xff26), 0xf1);
+        assert_eq!(apu.get8(0xff11), 0xbf);
+
+        apu.tick(CLOCK_FREQUENCY / 64);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), DEFAULT_SAMPLE_RATE as usize / 64 * 2);
+        assert!(samples.iter().any(|s| *s > 0.1));
+        assert!(samples.iter().any(|s| *s < -0.1));
+
+        // Length counter: 64 steps of 256 Hz.
+        apu.set8(0xff11, 0xbf);
+        apu.set8(0xff14, 0xc7);
+        for _ in 0..2 {
+            apu.step_frame_sequencer();
+        }
+        assert_eq!(apu.get8(0xff26), 0xf0);
+
+        apu.set8(0xff26, 0x00);
+        assert_eq!(apu.get8(0xff26), 0x70);
+        assert_eq!(apu.get8(0xff12), 0x00);
+    }
+
+    #[test]
+    fn test_wave_channel() {
+        let mut apu = Apu::new(DEFAULT_SAMPLE_RATE);
+        apu.set8(0xff26, 0x80);
+        apu.set8(0xff30, 0xc3);
+        apu.set8(0xff3f, 0x5a);
+        assert_eq!(apu.get8(0xff30), 0xc3);
+        assert_eq!(apu.get8(0xff3f), 0x5a);
+
+        // DAC on, full volume, trigger: the first sample is the upper nibble.
+        apu.set8(0xff1a, 0x80);
+        apu.set8(0xff1c, 0x20);
+        apu.set8(0xff1e, 0x80);
+        assert_eq!(apu.get8(0xff26), 0xf4);
+        assert_eq!(apu.pcm(0xff77) & 0x0f, 0x0c);
+        // The volume code shifts the sample right, code 0 mutes it.
+        for (nr32, output) in [(0x40, 0x06), (0x60, 0x03), (0x00, 0x00)] {
+            apu.set8(0xff1c, nr32);
+            assert_eq!(apu.pcm(0xff77) & 0x0f, output);
+        }
+
+        // One period of frequency 0 later, the lower nibble.
+        apu.set8(0xff1c, 0x20);
+        apu.tick(2048 * 2);
+        assert_eq!(apu.pcm(0xff77) & 0x0f, 0x03);
+
+        // The wave RAM is kept when the APU is powered off.
+        apu.set8(0xff26, 0x00);
+        assert_eq!(apu.get8(0xff30), 0xc3);
+    }
+
+    /// Trigger the noise channel with NR43 and return the output after each of `n` shifts of the LFSR.
+    fn noise(nr43: u8, n: usize) -> Vec<bool> {
+        let mut apu = Apu::new(DEFAULT_SAMPLE_RATE);
+        apu.set8(0xff26, 0x80);
+        apu.set8(0xff21, 0xf0);
+        apu.set8(0xff22, nr43);
+        apu.set8(0xff23, 0x80);
+        (0..n)
+            .map(|_| {
+                // Divisor 8, no shift.
+                apu.tick(8);
+                apu.pcm(0xff77) >> 4 != 0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_noise_channel() {
+        // The 7-bit LFSR repeats every 127 shifts.
+        let short = noise(0x08, 254);
+        assert!(short[..127].iter().any(|b| *b) && short[..127].iter().any(|b| !*b));
+        assert_eq!(short[..127], short[127..]);
+
+        // The 15-bit one every 32767.
+        let long = noise(0x00, 32767 + 200);
+        assert_eq!(long[..200], long[32767..]);
+        assert_ne!(long[..127], long[127..254]);
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut apu = Apu::new(DEFAULT_SAMPLE_RATE);
+        apu.set8(0xff26, 0x80);
+        apu.set8(0xff12, 0xf0);
+
+        // Pace 1, addition, shift 1: 0x600 + 0x300 overflows right at the trigger.
+        apu.set8(0xff10, 0x11);
+        apu.set8(0xff13, 0x00);
+        apu.set8(0xff14, 0x86);
+        assert_eq!(apu.get8(0xff26) & 0x01, 0x00);
+
+        // 0x400 + 0x200 fits, the channel is disabled once the new frequency is checked again by the sweep.
+        apu.set8(0xff14, 0x84);
+        assert_eq!(apu.get8(0xff26) & 0x01, 0x01);
+        for _ in 0..3 {
+            apu.step_frame_sequencer();
+        }
+        assert_eq!(apu.square1.frequency, 0x600);
+        assert_eq!(apu.get8(0xff26) & 0x01, 0x00);
+
+        // Subtraction never overflows.
+        apu.set8(0xff10, 0x19);
+        apu.set8(0xff14, 0x84);
+        for _ in 0..64 {
+            apu.step_frame_sequencer();
+        }
+        assert_eq!(apu.get8(0xff26) & 0x01, 0x01);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Stream of interleaved stereo samples to the default output device.
+///
+/// The emulator pushes samples into a queue which the device callback drains. When the queue runs dry the device
+/// plays silence. The device counts the stereo frames it has played, which is the clock the emulation is paced on, see
+/// `Pacer`.
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+pub struct Audio {
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    played: Arc<AtomicU64>,
+    sample_rate: u32,
+    #[cfg(feature = "audio")]
+    _stream: cpal::Stream,
+}
+
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+impl Audio {
+    /// Open the default output device. Returns `None` if there is none, or if the frontend was built without the
+    /// `audio` feature.
+    #[cfg(feature = "audio")]
+    pub fn open() -> Option<Self> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let device = cpal::default_host().default_output_device()?;
+        let supported = device.default_output_config().ok()?;
+        let config = supported.config();
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let played = Arc::new(AtomicU64::new(0));
+        let (q, p) = (queue.clone(), played.clone());
+        let stream = match supported.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, q, p),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, q, p),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, q, p),
+        }?;
+        stream.play().ok()?;
+        Some(Self {
+            queue,
+            played,
+            sample_rate: config.sample_rate.0,
+            _stream: stream,
+        })
+    }
+
+    #[cfg(not(feature = "audio"))]
+    pub fn open() -> Option<Self> {
+        None
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Queue interleaved stereo samples.
+    pub fn push(&self, samples: &[f32]) {
+        self.queue.lock().unwrap().extend(samples);
+    }
+
+    /// Number of stereo frames waiting to be played.
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len() / 2
+    }
+
+    /// Number of stereo frames played by the device since it was opened, silence included.
+    pub fn played(&self) -> u64 {
+        self.played.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "audio")]
+fn build_stream<T: cpal::Sample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    played: Arc<AtomicU64>,
+) -> Option<cpal::Stream> {
+    use cpal::traits::DeviceTrait;
+
+    let channels = config.channels as usize;
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut queue = queue.lock().unwrap();
+                played.fetch_add((data.len() / channels) as u64, Ordering::Relaxed);
+                for frame in data.chunks_mut(channels) {
+                    let left = queue.pop_front().unwrap_or(0.0);
+                    let right = queue.pop_front().unwrap_or(0.0);
+                    // Mono devices get both sides, extra channels stay silent.
+                    match frame {
+                        [mono] => *mono = T::from(&((left + right) / 2.0)),
+                        [l, r, rest @ ..] => {
+                            *l = T::from(&left);
+                            *r = T::from(&right);
+                            for s in rest {
+                                *s = T::from(&0.0f32);
+                            }
+                        }
+                        [] => (),
+                    }
+                }
+            },
+            |err| eprintln!("audio stream error: {}", err),
+        )
+        .ok()
+}
@@ -0,0 +1,165 @@
+//! Run a ROM without a window, for automated tests on machines with no display.
+//!
+//! ```sh
+//! headless <rom> [--frames N] [--until-pc ADDR] [--until-serial TEXT] [--png PATH] [--hash]
+//! ```
+//!
+//! The ROM runs for N frames (600 by default), or until the CPU reaches ADDR (hexadecimal) or TEXT is received on the
+//! serial port within them. The final framebuffer is then written as a PNG and/or its hash is printed, and the serial
+//! output goes to stderr. The exit code is 1 if the stop condition was not met, 2 for a usage error.
+
+use std::{fs::File, io::BufWriter, path::PathBuf, process::ExitCode};
+
+use gb_emulator::{
+    gameboy::{GameBoy, FRAME_CYCLES},
+    gpu::{SCREEN_H, SCREEN_W},
+    serial::StringSink,
+};
+
+const USAGE: &str =
+    "usage: headless <rom> [--frames N] [--until-pc ADDR] [--until-serial TEXT] [--png PATH] [--hash]";
+
+struct Options {
+    rom: PathBuf,
+    frames: u32,
+    until_pc: Option<u16>,
+    until_serial: Option<String>,
+    png: Option<PathBuf>,
+    hash: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut rom = None;
+        let mut options = Options {
+            rom: PathBuf::new(),
+            frames: 600,
+            until_pc: None,
+            until_serial: None,
+            png: None,
+            hash: false,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--frames" => {
+                    options.frames = value()?.parse().map_err(|e| format!("--frames: {}", e))?
+                }
+                "--until-pc" => {
+                    let v = value()?;
+                    let v = v.trim_start_matches("0x");
+                    options.until_pc =
+                        Some(u16::from_str_radix(v, 16).map_err(|e| format!("--until-pc: {}", e))?);
+                }
+                "--until-serial" => options.until_serial = Some(value()?),
+                "--png" => options.png = Some(PathBuf::from(value()?)),
+                "--hash" => options.hash = true,
+                _ if rom.is_none() && !arg.starts_with("--") => rom = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+        options.rom = rom.ok_or_else(|| USAGE.to_string())?;
+        Ok(options)
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let mut gameboy = match GameBoy::from_path(options.rom.clone()) {
+        Ok(gameboy) => gameboy,
+        Err(e) => {
+            eprintln!("{}: {}", options.rom.display(), e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let serial = StringSink::new();
+    gameboy.set_serial_sink(Box::new(serial.clone()));
+
+    let budget = u64::from(options.frames) * u64::from(FRAME_CYCLES);
+    let mut cycles = 0;
+    let mut reached = options.until_pc.is_none() && options.until_serial.is_none();
+    while cycles < budget {
+        cycles += u64::from(gameboy.step());
+        if Some(gameboy.pc()) == options.until_pc
+            || matches!(options.until_serial.as_ref(), Some(text) if serial.ends_with(text))
+        {
+            reached = true;
+            break;
+        }
+    }
+    let output = serial.output();
+    if !output.is_empty() {
+        eprintln!("{}", output);
+    }
+    eprintln!(
+        "ran {} frames, pc={:04x}",
+        cycles / u64::from(FRAME_CYCLES),
+        gameboy.pc()
+    );
+
+    let frame = gameboy.frame();
+    if options.hash {
+        println!("{:016x}", hash(&frame));
+    }
+    if let Some(path) = options.png.as_ref() {
+        if let Err(e) = write_png(path, &frame) {
+            eprintln!("{}: {}", path.display(), e);
+            return ExitCode::from(2);
+        }
+    }
+    if reached {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// FNV-1a of the framebuffer. Unlike the hasher of the standard library, it is stable across Rust versions, so it can
+/// be stored in golden tests.
+fn hash(frame: &[[[u8; 3]; SCREEN_W]; SCREEN_H]) -> u64 {
+    frame
+        .iter()
+        .flatten()
+        .flatten()
+        .fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+fn write_png(path: &PathBuf, frame: &[[[u8; 3]; SCREEN_W]; SCREEN_H]) -> std::io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        SCREEN_W as u32,
+        SCREEN_H as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = frame.iter().flatten().flatten().copied().collect();
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_options() {
+        let args = ["rom.gb", "--frames", "10", "--until-pc", "0x0150", "--hash"];
+        let options = Options::parse(args.iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(options.rom, PathBuf::from("rom.gb"));
+        assert_eq!(options.frames, 10);
+        assert_eq!(options.until_pc, Some(0x0150));
+        assert!(options.hash);
+        assert!(options.png.is_none());
+
+        assert!(Options::parse(["--hash".to_string()].into_iter()).is_err());
+    }
+}
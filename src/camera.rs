@@ -0,0 +1,137 @@
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+/// Size of the picture captured by the sensor of the Game Boy Camera.
+pub const SENSOR_W: usize = 128;
+pub const SENSOR_H: usize = 112;
+
+/// What the Game Boy Camera looks at, without a webcam: a still picture, or a sequence of pictures played in a loop.
+///
+/// Every picture is turned into 8-bit grayscale and cropped around its center to the aspect ratio of the sensor,
+/// then scaled to `SENSOR_W` x `SENSOR_H`.
+pub struct ImageSequence {
+    images: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl ImageSequence {
+    /// A PNG file, or a directory of PNG files played in the order of their names.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut paths = if path.is_dir() {
+            let mut paths = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            paths.retain(|path| {
+                path.extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+            });
+            paths
+        } else {
+            vec![path.to_path_buf()]
+        };
+        if paths.is_empty() {
+            return Err(io::Error::new(ErrorKind::NotFound, "no PNG file"));
+        }
+        paths.sort();
+        let images = paths
+            .iter()
+            .map(|path| load_png(path))
+            .collect::<io::Result<_>>()?;
+        Ok(Self { images, next: 0 })
+    }
+
+    /// The next picture of the sequence, `SENSOR_W` x `SENSOR_H` bytes of luminance, one per pixel.
+    pub fn next_image(&mut self) -> &[u8] {
+        let i = self.next;
+        self.next = (self.next + 1) % self.images.len();
+        &self.images[i]
+    }
+}
+
+fn load_png(path: &Path) -> io::Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let luminance = |x: usize, y: usize| {
+        let pixel = &data[y * info.line_size + x * channels..][..channels];
+        match info.color_type {
+            png::ColorType::Rgb | png::ColorType::Rgba => {
+                ((u32::from(pixel[0]) * 299
+                    + u32::from(pixel[1]) * 587
+                    + u32::from(pixel[2]) * 114)
+                    / 1000) as u8
+            }
+            _ => pixel[0],
+        }
+    };
+
+    // The largest centered area with the aspect ratio of the sensor.
+    let (crop_w, crop_h) = if width * SENSOR_H > height * SENSOR_W {
+        (height * SENSOR_W / SENSOR_H, height)
+    } else {
+        (width, width * SENSOR_H / SENSOR_W)
+    };
+    let (left, top) = ((width - crop_w) / 2, (height - crop_h) / 2);
+    let mut image = Vec::with_capacity(SENSOR_W * SENSOR_H);
+    for y in 0..SENSOR_H {
+        for x in 0..SENSOR_W {
+            image.push(luminance(
+                left + x * crop_w / SENSOR_W,
+                top + y * crop_h / SENSOR_H,
+            ));
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufWriter;
+
+    use super::*;
+
+    #[test]
+    fn test_load_png() {
+        // Twice as wide as the sensor: a white band on each side is cropped away, the rest is red then blue.
+        let (width, height) = (SENSOR_W * 4, SENSOR_H * 2);
+        let mut data = Vec::with_capacity(width * height * 3);
+        for _ in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(match x * 4 / width {
+                    0 | 3 => &[0xff, 0xff, 0xff],
+                    1 => &[0xff, 0x00, 0x00],
+                    _ => &[0x00, 0x00, 0xff],
+                });
+            }
+        }
+        let path = std::env::temp_dir().join(format!("gb-emulator-{}.png", std::process::id()));
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(&path).unwrap()),
+            width as u32,
+            height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&data)
+            .unwrap();
+
+        let image = load_png(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(image.len(), SENSOR_W * SENSOR_H);
+        for y in [0, SENSOR_H - 1] {
+            assert_eq!(image[y * SENSOR_W], 76);
+            assert_eq!(image[y * SENSOR_W + SENSOR_W / 2 - 1], 76);
+            assert_eq!(image[y * SENSOR_W + SENSOR_W / 2], 29);
+            assert_eq!(image[y * SENSOR_W + SENSOR_W - 1], 29);
+        }
+    }
+}
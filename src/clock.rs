@@ -11,7 +11,7 @@ impl Clock {
     pub fn next(&mut self, cycles: u32) -> u32 {
         self.n += cycles;
         let rs = self.n / self.period;
-        self.n = self.n % self.period;
+        self.n %= self.period;
         rs
     }
 }
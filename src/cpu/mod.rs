@@ -1,9 +1,6 @@
-use std::{cell::RefCell, rc::Rc, sync::Arc};
+use std::{cell::RefCell, rc::Rc};
 
-use crate::{
-    mbc::CartridgeHeader,
-    memory::{Memory, MemoryIO},
-};
+use crate::memory::{Memory, MemoryIO};
 
 use self::register::{Flag, Register};
 
@@ -19,7 +16,7 @@ mod register;
 //  0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
 const OP_CYCLES: [u32; 256] = [
     1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0
-    0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 1
+    1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 1
     2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 2
     2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 3
     1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 4
@@ -57,8 +54,6 @@ const CB_CYCLES: [u32; 256] = [
 ];
 
 pub const CLOCK_FREQUENCY: u32 = 4_194_304;
-pub const STEP_TIME: u32 = 16;
-pub const STEP_CYCLES: u32 = (STEP_TIME as f64 / (1000_f64 / CLOCK_FREQUENCY as f64)) as u32;
 
 /// # A Z80-like CPU struct
 ///
@@ -75,6 +70,8 @@ pub struct Cpu {
     memory: Rc<RefCell<Memory>>,
     is_interrupt_enabled: bool,
     is_halted: bool,
+    /// Stopped by STOP, only a key press wakes the CPU up.
+    is_stopped: bool,
 }
 
 impl Cpu {
@@ -84,6 +81,7 @@ impl Cpu {
             memory,
             is_interrupt_enabled: true,
             is_halted: false,
+            is_stopped: false,
         }
     }
 
@@ -123,13 +121,26 @@ impl Cpu {
         // Serial: 0x58
         // JOYPAD: 0x60
         self.register.pc = 0x0040 | ((n as u16) << 3);
-        4
+        // Two wait states, the push and the jump: 5 M-cycles.
+        5
+    }
+
+    /// Address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.register.pc
     }
 
     /// actually simulating the CPU workflow
     /// interrupt - fetch - execute
-    fn tick(&mut self) -> u32 {
+    ///
+    /// Returns the number of clock cycles spent.
+    pub fn tick(&mut self) -> u32 {
         let mac = {
+            if self.is_stopped {
+                // Any selected key held down on P1 ends STOP mode.
+                self.is_stopped = self.memory.borrow().get8(0xff00) & 0x0f == 0x0f;
+                return OP_CYCLES[0] * 4;
+            }
             let c = self.handle_interrupt();
             if c != 0 {
                 c
@@ -604,7 +615,14 @@ impl Cpu {
             0x76 => self.is_halted = true,
 
             // STOP
-            0x10 => (),
+            0x10 => {
+                // STOP is followed by a padding byte.
+                self.fetch8();
+                // On the CGB, STOP performs the speed switch requested through KEY1 instead of stopping.
+                if !self.memory.borrow_mut().switch_speed() {
+                    self.is_stopped = true;
+                }
+            }
 
             // DI
             0xf3 => self.is_interrupt_enabled = false,
@@ -1108,8 +1126,6 @@ impl Cpu {
                         temp = self.reset(temp, 7);
                         self.memory.borrow_mut().set8(self.register.get_hl(), temp);
                     }
-
-                    _ => (),
                 }
                 return CB_CYCLES[opcode2 as usize];
             }
@@ -1119,8 +1135,8 @@ impl Cpu {
     }
 
     fn push(&mut self, n: u16) {
+        self.register.set_sp(self.register.get_sp().wrapping_sub(2));
         self.memory.borrow_mut().set16(self.register.get_sp(), n);
-        self.register.set_sp(self.register.get_sp() - 2)
     }
 
     fn pop(&mut self, opcode: u8) {
@@ -1544,10 +1560,7 @@ impl Cpu {
 
     fn call(&mut self) {
         let address = self.fetch16();
-        self.memory
-            .borrow_mut()
-            .set16(self.register.get_sp(), self.register.pc);
-        self.register.set_sp(self.register.get_sp() - 2);
+        self.push(self.register.pc);
         self.register.pc = address;
     }
 
@@ -1564,10 +1577,7 @@ impl Cpu {
     }
 
     fn restart(&mut self, n: u8) {
-        self.memory
-            .borrow_mut()
-            .set16(self.register.get_sp(), self.register.pc);
-        self.register.set_sp(self.register.get_sp() - 2);
+        self.push(self.register.pc);
         self.register.pc = n as u16;
     }
 
@@ -1595,3 +1605,49 @@ impl Cpu {
         // enable interrupts
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        apu::{Apu, DEFAULT_SAMPLE_RATE},
+        gpu::Gpu,
+        interrupt::{IntFlag, Interrupt},
+        joypad::Joypad,
+        mbc::Cartridge,
+        serial::Serial,
+        Term,
+    };
+
+    #[test]
+    fn test_vblank_interrupt() {
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let gpu = Rc::new(RefCell::new(Gpu::new(Term::GB, interrupt.clone())));
+        let joypad = Rc::new(RefCell::new(Joypad::new(interrupt.clone())));
+        let apu = Rc::new(RefCell::new(Apu::new(DEFAULT_SAMPLE_RATE)));
+        let serial = Rc::new(RefCell::new(Serial::new(Term::GB, interrupt.clone())));
+        let memory = Rc::new(RefCell::new(Memory::new(
+            Term::GB,
+            Cartridge::try_from(vec![0; 0x8000]).unwrap(),
+            gpu.clone(),
+            joypad,
+            apu,
+            serial,
+            interrupt.clone(),
+        )));
+        let mut cpu = Cpu::new(memory.clone());
+        memory.borrow_mut().set8(0xffff, IntFlag::VBLANK.bits());
+
+        // Run the GPU through the 144 visible lines, it enters V-Blank right after.
+        gpu.borrow_mut().tick(144 * 456);
+        assert_eq!(
+            interrupt.borrow().request & IntFlag::VBLANK.bits(),
+            IntFlag::VBLANK.bits()
+        );
+
+        assert_eq!(cpu.tick(), 20);
+        assert_eq!(cpu.register.pc, 0x0040);
+        assert_eq!(memory.borrow().get16(cpu.register.sp), 0x0100);
+        assert_eq!(interrupt.borrow().request & IntFlag::VBLANK.bits(), 0);
+    }
+}
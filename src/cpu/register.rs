@@ -72,6 +72,7 @@ impl Register {
     }
 
     #[inline]
+    #[allow(dead_code)]
     pub fn get_f(&self) -> u8 {
         self.f
     }
@@ -122,6 +123,7 @@ impl Register {
     }
 
     #[inline]
+    #[allow(dead_code)]
     pub fn set_f(&mut self, n: u8) {
         self.f = n
     }
@@ -143,7 +145,7 @@ impl Register {
 
     #[inline]
     pub fn pc_inc(&mut self, n: i16) {
-        self.pc = (self.pc as i16 + n) as u16;
+        self.pc = self.pc.wrapping_add(n as u16);
     }
 }
 
@@ -1,23 +1,268 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use crate::{
-    cpu::Cpu,
-    gpu::Gpu,
-    mbc::CartridgeHeader,
-    memory::{self, Memory},
+    apu::{Apu, DEFAULT_SAMPLE_RATE},
+    clock::Clock,
+    cpu::{Cpu, CLOCK_FREQUENCY},
+    gpu::{Gpu, SCREEN_H, SCREEN_W},
+    infrared::InfraredSink,
+    interrupt::Interrupt,
+    joypad::{Buttons, Joypad},
+    mbc::{Accessory, Cartridge},
+    memory::Memory,
+    serial::{Serial, SerialSink},
+    Term,
 };
 
-struct GameBoy {
+/// A frame is 154 scan lines of 456 dots each, including the 10 lines of V-Blank.
+pub const FRAME_CYCLES: u32 = 456 * 154;
+/// About 59.73 frames per second.
+pub const FRAME_RATE: f64 = CLOCK_FREQUENCY as f64 / FRAME_CYCLES as f64;
+
+/// The whole machine.
+///
+/// This is the only entry point for embedding the emulator: call `step` to execute a single instruction, or
+/// `run_frame` to run until the next frame is ready, then read the picture with `frame`.
+pub struct GameBoy {
     cpu: Cpu,
     memory: Rc<RefCell<Memory>>,
     gpu: Rc<RefCell<Gpu>>,
+    joypad: Rc<RefCell<Joypad>>,
+    apu: Rc<RefCell<Apu>>,
+    serial: Rc<RefCell<Serial>>,
+    /// Counts the cycles spent in the current frame.
+    clock: Clock,
+    /// The cartridge memory is kept by a battery.
+    battery: bool,
+    /// Where the battery-backed memory is saved, see `attach_save`.
+    save_path: Option<PathBuf>,
 }
 
 impl GameBoy {
-    pub fn new(header: CartridgeHeader) -> Self {
-        let gpu = Rc::new(RefCell::new(Gpu::new()));
-        let memory = Rc::new(RefCell::new(Memory::new(header, gpu.clone())));
+    /// Build the machine around a cartridge. The mapper is picked from the cartridge type in the header.
+    pub fn from_cartridge(cartridge: Cartridge) -> Self {
+        let term = if cartridge.header().cgb_flag() {
+            Term::GBC
+        } else {
+            Term::GB
+        };
+        let battery = cartridge.header().battery();
+        // There is only one interrupt line: every peripheral raises its request into it, and the CPU services it
+        // through IF (0xff0f) and IE (0xffff) on the bus.
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let gpu = Rc::new(RefCell::new(Gpu::new(term, interrupt.clone())));
+        let joypad = Rc::new(RefCell::new(Joypad::new(interrupt.clone())));
+        let apu = Rc::new(RefCell::new(Apu::new(DEFAULT_SAMPLE_RATE)));
+        let serial = Rc::new(RefCell::new(Serial::new(term, interrupt.clone())));
+        let memory = Rc::new(RefCell::new(Memory::new(
+            term,
+            cartridge,
+            gpu.clone(),
+            joypad.clone(),
+            apu.clone(),
+            serial.clone(),
+            interrupt,
+        )));
         let cpu = Cpu::new(memory.clone());
-        Self { cpu, memory, gpu }
+        Self {
+            cpu,
+            memory,
+            gpu,
+            joypad,
+            apu,
+            serial,
+            clock: Clock::new(FRAME_CYCLES),
+            battery,
+            save_path: None,
+        }
+    }
+
+    /// Load a ROM file and build the machine around it. The save file is left alone, see `attach_save`.
+    pub fn from_path(path: PathBuf) -> io::Result<Self> {
+        Ok(Self::from_cartridge(Cartridge::new(path)?))
+    }
+
+    /// Keep the battery-backed memory of the cartridge in a `.sav` file: restore it from the file if it exists, and
+    /// write it back on `save` and `flush`. Does nothing if the cartridge has no battery.
+    pub fn attach_save(&mut self, path: PathBuf) -> io::Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        match fs::read(&path) {
+            Ok(save) => self.memory.borrow_mut().cartridge_mut().load(&save),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        self.save_path = Some(path);
+        Ok(())
+    }
+
+    /// Write the save file. Call it before dropping the machine, e.g. on exit.
+    pub fn save(&mut self) -> io::Result<()> {
+        match self.save_path.as_ref() {
+            Some(path) => fs::write(path, self.memory.borrow().cartridge().save()),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the save file if the game has disabled the cartridge RAM since the last call, which is when it is done
+    /// saving. Call it regularly, e.g. once per frame, so that little is lost if the emulator crashes.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.memory.borrow_mut().cartridge_mut().take_flush() {
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Execute one instruction (or service one interrupt), then advance every peripheral by the same number of
+    /// clock cycles. Returns the cycles spent, in normal speed clock cycles.
+    pub fn step(&mut self) -> u32 {
+        let cycles = self.cpu.tick();
+        self.memory.borrow_mut().tick(cycles)
+    }
+
+    /// Keep stepping until a full frame of 70224 dots has elapsed. The cycles that overshoot the frame boundary are
+    /// carried over to the next frame. Returns the cycles spent.
+    pub fn run_frame(&mut self) -> u32 {
+        let mut cycles = 0;
+        loop {
+            let c = self.step();
+            cycles += c;
+            if self.clock.next(c) != 0 {
+                return cycles;
+            }
+        }
+    }
+
+    /// Address of the next instruction, e.g. to stop a test ROM at a known point.
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    /// Update the keys held down by the host. Call it whenever the input changes, e.g. once per frame.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        self.joypad.borrow_mut().set_buttons(buttons);
+    }
+
+    /// Plug a device into the link port, e.g. a `StringSink` to read the output of test ROMs.
+    pub fn set_serial_sink(&mut self, sink: Box<dyn SerialSink>) {
+        self.serial.borrow_mut().set_sink(Some(sink));
+    }
+
+    /// Drive the hardware of the cartridge beyond its memory, e.g. take the changes of a rumble motor or tilt an
+    /// accelerometer. Returns `None` without calling `f` if the cartridge has none.
+    pub fn with_accessory<R>(&mut self, f: impl FnOnce(Accessory) -> R) -> Option<R> {
+        self.memory.borrow_mut().cartridge_mut().accessory().map(f)
+    }
+
+    /// Point the infrared port at a device. It is the one of the CGB, or the one of the cartridge for HuC1 and HuC3.
+    pub fn set_infrared_sink(&mut self, sink: Box<dyn InfraredSink>) {
+        self.memory
+            .borrow()
+            .infrared()
+            .borrow_mut()
+            .set_sink(Some(sink));
+    }
+
+    /// The link port, for wiring two machines together.
+    pub(crate) fn serial(&self) -> Rc<RefCell<Serial>> {
+        self.serial.clone()
+    }
+
+    /// Change the rate of the samples returned by `take_samples`, e.g. to the one of the host audio device.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.borrow_mut().set_sample_rate(sample_rate);
+    }
+
+    /// Take the audio produced since the last call, as interleaved left and right `f32` samples. Call it once per
+    /// frame, about 800 samples per channel at 48 kHz.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.apu.borrow_mut().take_samples()
+    }
+
+    /// The picture drawn by the GPU so far, in RGB.
+    pub fn frame(&self) -> [[[u8; 3]; SCREEN_W]; SCREEN_H] {
+        self.gpu.borrow().data
+    }
+}
+
+/// The save file of a ROM, `game.gb` is saved in `game.sav` like with other emulators.
+pub fn save_path(rom: &Path) -> PathBuf {
+    rom.with_extension("sav")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryIO;
+
+    #[test]
+    fn test_rom_reaches_the_bus() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x7fff] = 0x42;
+        let gameboy = GameBoy::from_cartridge(Cartridge::try_from(rom).unwrap());
+        let memory = gameboy.memory.borrow();
+        assert_eq!(memory.get8(0x134), b'T');
+        assert_eq!(memory.get8(0x7fff), 0x42);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        // 81 NOPs and a JR back to them: 84 cycles, which divides a frame evenly.
+        let mut rom = vec![0; 0x8000];
+        rom[0x151..0x153].copy_from_slice(&[0x18, 0xad]);
+        let mut gameboy = GameBoy::from_cartridge(Cartridge::try_from(rom).unwrap());
+        assert_eq!(gameboy.run_frame(), FRAME_CYCLES);
+        assert_eq!(gameboy.run_frame(), FRAME_CYCLES);
+        assert_eq!(gameboy.clock.n, 0);
+
+        // LD A,0 then JR back to it: 8 + 12 cycles, the first frame ends 4 cycles into an instruction.
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x3e, 0x00, 0x18, 0xfc]);
+        let mut gameboy = GameBoy::from_cartridge(Cartridge::try_from(rom).unwrap());
+        assert_eq!(gameboy.run_frame(), FRAME_CYCLES + 4);
+        assert_eq!(gameboy.clock.n, 4);
+        // The overshoot counts towards the next frame.
+        assert_eq!(gameboy.run_frame(), FRAME_CYCLES - 4);
+        assert_eq!(gameboy.clock.n, 0);
+    }
+
+    #[test]
+    fn test_save_file() {
+        let path = std::env::temp_dir().join(format!("gb-emulator-{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+        // MBC1+RAM+BATTERY with 8 KiB of RAM.
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let mut gameboy = GameBoy::from_cartridge(Cartridge::try_from(rom.clone()).unwrap());
+        gameboy.attach_save(path.clone()).unwrap();
+        {
+            let mut memory = gameboy.memory.borrow_mut();
+            memory.set8(0x0000, 0x0a);
+            memory.set8(0xa123, 0x42);
+        }
+        gameboy.flush().unwrap();
+        assert!(!path.exists());
+        gameboy.memory.borrow_mut().set8(0x0000, 0x00);
+        gameboy.flush().unwrap();
+        let save = fs::read(&path).unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0x123], 0x42);
+
+        let mut gameboy = GameBoy::from_cartridge(Cartridge::try_from(rom).unwrap());
+        gameboy.attach_save(path.clone()).unwrap();
+        gameboy.memory.borrow_mut().set8(0x0000, 0x0a);
+        assert_eq!(gameboy.memory.borrow().get8(0xa123), 0x42);
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -188,7 +188,7 @@ impl MemoryIO for LcdControl {
 
 /// LCD Status
 ///
-/// - LYC=LY STAT Interrupt source         (1=Enable) (Read/Write)
+/// - Bit 6 - LYC=LY STAT Interrupt source         (1=Enable) (Read/Write)
 /// - Bit 5 - Mode 2 OAM STAT Interrupt source     (1=Enable) (Read/Write)
 /// - Bit 4 - Mode 1 VBlank STAT Interrupt source  (1=Enable) (Read/Write)
 /// - Bit 3 - Mode 0 HBlank STAT Interrupt source  (1=Enable) (Read/Write)
@@ -207,10 +207,10 @@ struct LcdStatus {
 impl LcdStatus {
     pub fn new() -> Self {
         Self {
-            current_line_interrupt: true,
-            is_mode2_interrupt_enabled: true,
-            is_mode1_interrupt_enabled: true,
-            is_mode0_interrupt_enabled: true,
+            current_line_interrupt: false,
+            is_mode2_interrupt_enabled: false,
+            is_mode1_interrupt_enabled: false,
+            is_mode0_interrupt_enabled: false,
             current_line_flag: true,
             mode: 0,
         }
@@ -219,33 +219,33 @@ impl LcdStatus {
 
 impl MemoryIO for LcdStatus {
     fn get8(&self, _: u16) -> u8 {
-        let mut res = 0;
+        // Bit 7 is unused and always reads 1.
+        let mut res = 0x80;
         if self.current_line_interrupt {
-            res |= 0x80;
+            res |= 0x40;
         }
         if self.is_mode2_interrupt_enabled {
-            res |= 0x40;
+            res |= 0x20;
         }
         if self.is_mode1_interrupt_enabled {
-            res |= 0x20;
+            res |= 0x10;
         }
         if self.is_mode0_interrupt_enabled {
-            res |= 0x10;
+            res |= 0x08;
         }
         if self.current_line_flag {
-            res |= 0x08;
+            res |= 0x04;
         }
         res |= self.mode;
         res
     }
 
     fn set8(&mut self, _: u16, n: u8) {
-        self.current_line_interrupt = n & 0x80 != 0;
-        self.is_mode2_interrupt_enabled = n & 0x40 != 0;
-        self.is_mode1_interrupt_enabled = n & 0x20 != 0;
-        self.is_mode0_interrupt_enabled = n & 0x10 != 0;
-        self.current_line_flag = n & 0x08 != 0;
-        self.mode = n & 0x03;
+        // The LYC=LY flag and the mode are read only.
+        self.current_line_interrupt = n & 0x40 != 0;
+        self.is_mode2_interrupt_enabled = n & 0x20 != 0;
+        self.is_mode1_interrupt_enabled = n & 0x10 != 0;
+        self.is_mode0_interrupt_enabled = n & 0x08 != 0;
     }
 
     fn get16(&self, _: u16) -> u16 {
@@ -387,17 +387,17 @@ impl ColorPalette {
 
     pub fn get_color(&self, index: u8) -> (u8, u8, u8) {
         let r = self.data[index as usize] & 0x1f;
-        let g = self.data[index as usize] & 0x3e;
-        let b = self.data[index as usize] & 0x7c;
+        let g = (self.data[index as usize] >> 5) & 0x1f;
+        let b = (self.data[index as usize] >> 10) & 0x1f;
         (r as u8, g as u8, b as u8)
     }
 
-    pub fn set_color(&mut self, color: (u8, u8, u8)) {
+    pub fn set_color(&mut self, index: u8, color: (u8, u8, u8)) {
         let mut new = 0u16;
         new |= (color.0 & 0x1f) as u16;
         new |= ((color.1 & 0x1f) as u16) << 5;
         new |= ((color.2 & 0x1f) as u16) << 10;
-        self.data[self.index] = new;
+        self.data[index as usize] = new;
     }
 }
 
@@ -407,8 +407,11 @@ impl MemoryIO for ColorPalette {
             0x00 => self.index as u8 & 0x3f | if self.auto_increment { 0x80 } else { 0 },
             0x01 => {
                 let i = self.index >> 1;
-                let mask: u16 = if self.index & 1 == 0 { 0x00ff } else { 0xff00 };
-                (self.data[i] & mask) as u8
+                if self.index & 1 == 0 {
+                    self.data[i] as u8
+                } else {
+                    (self.data[i] >> 8) as u8
+                }
             }
             _ => unimplemented!(),
         }
@@ -427,13 +430,14 @@ impl MemoryIO for ColorPalette {
                 } else {
                     self.data[i] = (self.data[i] & 0x00ff) | ((n as u16) << 8);
                 }
+                // Only writing to the data register increments the index.
+                if self.auto_increment {
+                    self.index += 1;
+                    self.index &= 0x3f;
+                }
             }
             _ => unimplemented!(),
         }
-        if self.auto_increment {
-            self.index += 1;
-            self.index &= 0x3f;
-        }
     }
 
     fn get16(&self, _: u16) -> u16 {
@@ -468,21 +472,31 @@ pub struct Gpu {
     object_palette: ColorPalette,
 
     ram_bank: u8,
+    /// OPRI: object priority mode (CGB only). 0 for by OAM position, 1 for by X coordinate.
+    object_priority_mode: u8,
     // BGP, OBP0 and OBP1, and BCPS/BGPI, BCPD/BGPD, OCPS/OBPI and OCPD/OBPD (CGB Mode).
+    /// The mode the PPU has last entered, the interrupts are requested when it changes.
     mode: u8,
     prio: [(bool, usize); SCREEN_W],
 
     /// 1 dots 是1/4.19M秒。
     dots: u32,
+    /// Mode 0 has been entered since the last `take_hblank`, for the H-Blank DMA.
+    hblank: bool,
     interrupt: Rc<RefCell<Interrupt>>,
 
     pub data: [[[u8; 3]; SCREEN_W]; SCREEN_H],
 }
 
 impl Gpu {
-    pub fn new() -> Self {
+    pub fn new(term: Term, interrupt: Rc<RefCell<Interrupt>>) -> Self {
+        // The CGB boot ROM leaves every background color white.
+        let mut background_palette = ColorPalette::new();
+        for i in 0..32 {
+            background_palette.set_color(i, (0x1f, 0x1f, 0x1f));
+        }
         Self {
-            term: Term::GB,
+            term,
             vram: [0; 0x4000],
             oam: [OAMEntry::default(); 40],
             scrollx: 0,
@@ -501,9 +515,11 @@ impl Gpu {
             prio: [(true, 0); SCREEN_W],
             dots: 0,
             ram_bank: 0,
-            background_palette: ColorPalette::new(),
+            object_priority_mode: 0,
+            hblank: false,
+            background_palette,
             object_palette: ColorPalette::new(),
-            interrupt: Rc::new(RefCell::new(Interrupt::new())),
+            interrupt,
 
             data: [[[0xffu8; 3]; SCREEN_W]; SCREEN_H],
         }
@@ -542,10 +558,20 @@ impl Gpu {
         self.data[self.lcd_y_coordinate as usize][x] = [lr, lg, lb];
     }
 
+    /// Whether an H-Blank has started since the last call.
+    pub fn take_hblank(&mut self) -> bool {
+        std::mem::take(&mut self.hblank)
+    }
+
+    /// Whether the H-Blank DMA may copy a block right away: during H-Blank, or any time the LCD is off.
+    pub fn in_hblank(&self) -> bool {
+        !self.lcd_control.lcd_and_ppu_enable || self.lcd_status.mode == 0
+    }
+
     /// 往前走若干个始终周期
     ///
     /// cycles：周期数
-    fn tick(&mut self, cycles: u32) {
+    pub fn tick(&mut self, cycles: u32) {
         // 首先检查LCD是不是已经启用了，如果没启用就直接返回。
         if !self.lcd_control.lcd_and_ppu_enable {
             return;
@@ -560,16 +586,17 @@ impl Gpu {
         let c = (cycles - 1) / 80 + 1; // 项上取整
         for i in 0..c {
             if i == c - 1 {
-                self.dots += cycles & 80;
+                self.dots += cycles - 80 * i;
             } else {
                 self.dots += 80;
             }
 
-            if self.dots != self.dots % 456 {
+            // 一行是456 dots，走完一行就换到下一行
+            if self.dots >= 456 {
+                self.dots -= 456;
                 self.lcd_y_coordinate = (self.lcd_y_coordinate + 1) % 154;
-                if self.lcd_status.current_line_interrupt
-                    && self.lcd_y_coordinate == self.ly_compare
-                {
+                self.lcd_status.current_line_flag = self.lcd_y_coordinate == self.ly_compare;
+                if self.lcd_status.current_line_interrupt && self.lcd_status.current_line_flag {
                     self.interrupt
                         .borrow_mut()
                         .request_interrupt(IntFlag::LCDSTAT);
@@ -580,21 +607,27 @@ impl Gpu {
     }
 
     /// 这里主要控制中断，不同模式的中断不一样
+    ///
+    /// 只有在模式切换的那一刻才会请求中断、渲染扫描线。
     fn change_mode(&mut self) {
-        if self.lcd_y_coordinate < 144 {
-            if self.dots % 456 < 80 {
-                self.lcd_status.mode = 2;
-            } else if self.dots % 456 >= 80 && self.dots % 456 < (80 + 172) {
-                self.lcd_status.mode = 3;
-            } else if self.dots % 456 >= 80 + 172 {
-                self.lcd_status.mode = 0;
-            }
+        let mode = if self.lcd_y_coordinate >= 144 {
+            1
+        } else if self.dots < 80 {
+            2
+        } else if self.dots < 80 + 172 {
+            3
         } else {
-            self.lcd_status.mode = 1;
+            0
+        };
+        if mode == self.mode {
+            return;
         }
+        self.mode = mode;
+        self.lcd_status.mode = mode;
 
-        match self.mode {
+        match mode {
             0 => {
+                self.hblank = true;
                 if self.lcd_status.is_mode0_interrupt_enabled {
                     self.interrupt
                         .borrow_mut()
@@ -618,15 +651,10 @@ impl Gpu {
                         .request_interrupt(IntFlag::LCDSTAT);
                 }
             }
-            2 => {
-                if self.lcd_status.is_mode2_interrupt_enabled {
-                    self.interrupt
-                        .borrow_mut()
-                        .request_interrupt(IntFlag::LCDSTAT);
-                }
-            }
-            3 => {
-                self.mode = 3;
+            2 if self.lcd_status.is_mode2_interrupt_enabled => {
+                self.interrupt
+                    .borrow_mut()
+                    .request_interrupt(IntFlag::LCDSTAT);
             }
             _ => (),
         }
@@ -684,17 +712,9 @@ impl Gpu {
                 pixel_y % 8
             };
             let tile_y_data = if self.term == Term::GBC {
-                let a = self.vram[(tile_location
-                    + tile_y as u16 * 2
-                    + (tile_attribute.tile_bank as u16)
-                    << 13) as usize
-                    - 0x8000];
-                let b = self.vram[(tile_location
-                    + tile_y as u16 * 2
-                    + (tile_attribute.tile_bank as u16)
-                    << 13) as usize
-                    - 0x8000
-                    + 1];
+                let bank = (tile_attribute.tile_bank as usize) << 13;
+                let a = self.vram[bank + (tile_location + tile_y as u16 * 2) as usize - 0x8000];
+                let b = self.vram[bank + (tile_location + tile_y as u16 * 2) as usize - 0x8000 + 1];
                 (a, b)
             } else {
                 let a = self.vram[(tile_location + tile_y as u16 * 2) as usize - 0x8000];
@@ -768,15 +788,9 @@ impl Gpu {
             };
             let tile_location = 0x8000 + sprite.tile_index as u16 * 16 + tile_y as u16 * 2;
             let tile_y_data = if self.term == Term::GBC {
-                let a =
-                    self.vram[(tile_location + tile_y as u16 * 2 + (sprite.flags.tile_bank as u16)
-                        << 13) as usize
-                        - 0x8000];
-                let b =
-                    self.vram[(tile_location + tile_y as u16 * 2 + (sprite.flags.tile_bank as u16)
-                        << 13) as usize
-                        - 0x8000
-                        + 1];
+                let bank = (sprite.flags.tile_bank as usize) << 13;
+                let a = self.vram[bank + (tile_location + tile_y as u16 * 2) as usize - 0x8000];
+                let b = self.vram[bank + (tile_location + tile_y as u16 * 2) as usize - 0x8000 + 1];
                 (a, b)
             } else {
                 let a = self.vram[(tile_location + tile_y as u16 * 2) as usize - 0x8000];
@@ -785,7 +799,7 @@ impl Gpu {
             };
 
             for x in 0..8 {
-                if sprite.x_position as usize + x as usize > SCREEN_W {
+                if sprite.x_position as usize + x > SCREEN_W {
                     continue;
                 }
                 let tile_x = if sprite.flags.is_x_flipped { 7 - x } else { x };
@@ -820,7 +834,7 @@ impl Gpu {
 
                 if self.term == Term::GBC {
                     let (r, g, b) = self
-                        .background_palette
+                        .object_palette
                         .get_color(sprite.flags.palette_number_cgb * 4 + color);
                     self.set_rgb(sprite.x_position.wrapping_add(x as u8) as usize, r, g, b);
                 } else {
@@ -852,13 +866,14 @@ impl MemoryIO for Gpu {
             0xff43 => self.scrollx,
             0xff44 => self.lcd_y_coordinate,
             0xff45 => self.ly_compare,
-            0xff46 => 0, // DMA
+            0xff46 => 0, // DMA, performed by the bus
             0xff47 => self.bg_palette_data,
             0xff48 => self.obj_palette_0,
             0xff49 => self.obj_palette_1,
             0xff4a => self.wndposy,
             0xff4b => self.wndposx,
             0xff4f => 0xfe | self.ram_bank,
+            0xff6c => 0xfe | self.object_priority_mode,
             0xff68 => self.background_palette.get8(address), // BGPI, Background color palette specification / Background palette index
             0xff69 => self.background_palette.get8(address), // BGPD, Background color palette data / Background palette data
             0xff6a => self.object_palette.get8(address), // OBPI, OBJ color palette specification / OBJ palette index
@@ -878,6 +893,7 @@ impl MemoryIO for Gpu {
                 if !self.lcd_control.lcd_and_ppu_enable {
                     self.dots = 0;
                     self.lcd_y_coordinate = 0;
+                    self.mode = 0;
                     self.lcd_status.mode = 0;
                     // Clean screen.
                     self.data = [[[0xffu8; 3]; SCREEN_W]; SCREEN_H];
@@ -886,15 +902,17 @@ impl MemoryIO for Gpu {
             0xff41 => self.lcd_status.set8(address, n),
             0xff42 => self.scrolly = n,
             0xff43 => self.scrollx = n,
-            0xff44 => self.lcd_y_coordinate = n,
+            // LY is read only.
+            0xff44 => (),
             0xff45 => self.ly_compare = n,
-            0xff46 => (), // DMA
+            0xff46 => (), // DMA, performed by the bus
             0xff47 => self.bg_palette_data = n,
             0xff48 => self.obj_palette_0 = n,
             0xff49 => self.obj_palette_1 = n,
             0xff4a => self.wndposy = n,
             0xff4b => self.wndposx = n,
-            0xff4f => self.ram_bank = 0xfe | n,
+            0xff4f => self.ram_bank = n & 0x01,
+            0xff6c => self.object_priority_mode = n & 0x01,
             0xff68 => self.background_palette.set8(address, n), // BGPI, Background color palette specification / Background palette index
             0xff69 => self.background_palette.set8(address, n), // BGPD, Background color palette data / Background palette data
             0xff6a => self.object_palette.set8(address, n), // OBPI, OBJ color palette specification / OBJ palette index
@@ -916,6 +934,9 @@ impl MemoryIO for Gpu {
     }
 }
 
+/// Decode the 16 bytes of a tile into its 64 color numbers. The renderer decodes the row it needs instead, this
+/// checks the bit order it relies on.
+#[cfg(test)]
 fn data_to_tile(data: [u8; 16]) -> [u8; 64] {
     let mut tile = [0u8; 64];
     for i in 0..8 {
@@ -0,0 +1,126 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::memory::MemoryIO;
+
+/// The device facing the infrared port: another Game Boy Color, a toy, a TV remote...
+pub trait InfraredSink {
+    /// The LED of this side is turned on or off.
+    fn led(&mut self, on: bool);
+
+    /// Whether light from the other side reaches the sensor of this side.
+    fn light(&mut self) -> bool {
+        false
+    }
+}
+
+/// The infrared LED and sensor, the one of the CGB (RP, 0xff56) and the one of the HuC1 and HuC3 cartridges share
+/// the same host interface.
+pub struct Infrared {
+    led: bool,
+    sink: Option<Box<dyn InfraredSink>>,
+}
+
+impl Infrared {
+    pub fn new() -> Self {
+        Self {
+            led: false,
+            sink: None,
+        }
+    }
+
+    /// Point the port at a device, or at nothing with `None`.
+    pub fn set_sink(&mut self, sink: Option<Box<dyn InfraredSink>>) {
+        self.sink = sink;
+    }
+
+    pub fn led(&self) -> bool {
+        self.led
+    }
+
+    /// The sink only sees the changes.
+    pub fn set_led(&mut self, on: bool) {
+        if on != self.led {
+            self.led = on;
+            if let Some(sink) = self.sink.as_mut() {
+                sink.led(on);
+            }
+        }
+    }
+
+    /// Whether the sensor sees light. Without a device, it's dark.
+    pub fn light(&mut self) -> bool {
+        self.sink.as_mut().is_some_and(|sink| sink.light())
+    }
+}
+
+/// RP (CGB only)
+///
+/// - Bit 7-6 - Data Read Enable (0=Disable, 3=Enable)
+/// - Bit 1   - Read Data        (0=Receiving IR Signal, 1=Normal) (Read Only)
+/// - Bit 0   - Write Data       (0=LED Off, 1=LED On)
+pub struct Rp {
+    rp: u8,
+    infrared: Rc<RefCell<Infrared>>,
+}
+
+impl Rp {
+    pub fn new(infrared: Rc<RefCell<Infrared>>) -> Self {
+        Self { rp: 0, infrared }
+    }
+}
+
+impl MemoryIO for Rp {
+    fn get8(&self, _: u16) -> u8 {
+        let light = self.rp & 0xc0 == 0xc0 && self.infrared.borrow_mut().light();
+        0x3c | self.rp | if light { 0x00 } else { 0x02 }
+    }
+
+    fn set8(&mut self, _: u16, n: u8) {
+        self.rp = n & 0xc1;
+        self.infrared.borrow_mut().set_led(n & 0x01 != 0);
+    }
+
+    fn get16(&self, _: u16) -> u16 {
+        unimplemented!("RP doesn't support reading 2-byte data.")
+    }
+
+    fn set16(&mut self, _: u16, _: u16) {
+        unimplemented!("RP doesn't support writing 2-byte data.")
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Sees its own LED, like with a mirror in front of the port. Shared with the tests of the cartridges.
+    pub(crate) struct Mirror(pub(crate) bool);
+
+    impl InfraredSink for Mirror {
+        fn led(&mut self, on: bool) {
+            self.0 = on;
+        }
+
+        fn light(&mut self) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_rp() {
+        let infrared = Rc::new(RefCell::new(Infrared::new()));
+        infrared
+            .borrow_mut()
+            .set_sink(Some(Box::new(Mirror(false))));
+        let mut rp = Rp::new(infrared.clone());
+        assert_eq!(rp.get8(0xff56), 0x3e);
+        rp.set8(0xff56, 0x01);
+        assert!(infrared.borrow().led());
+        // Reading is disabled.
+        assert_eq!(rp.get8(0xff56), 0x3f);
+        rp.set8(0xff56, 0xc1);
+        assert_eq!(rp.get8(0xff56), 0xfd);
+        rp.set8(0xff56, 0xc0);
+        assert_eq!(rp.get8(0xff56), 0xfe);
+    }
+}
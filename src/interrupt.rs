@@ -12,6 +12,10 @@ bitflags! {
     }
 }
 
+/// The interrupt line shared by every peripheral.
+///
+/// There is a single instance per machine, wrapped in `Rc<RefCell<>>`. The GPU, timer, serial port and joypad raise
+/// their requests into `request` (IF, 0xff0f), and the CPU services the ones that are also `enabled` (IE, 0xffff).
 pub struct Interrupt {
     pub request: u8,
     pub enabled: u8,
@@ -47,11 +51,11 @@ impl MemoryIO for Interrupt {
         }
     }
 
-    fn get16(&self, address: u16) -> u16 {
+    fn get16(&self, _: u16) -> u16 {
         unimplemented!()
     }
 
-    fn set16(&mut self, address: u16, n: u16) {
+    fn set16(&mut self, _: u16, _: u16) {
         unimplemented!()
     }
 }
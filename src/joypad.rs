@@ -0,0 +1,118 @@
+use std::{cell::RefCell, rc::Rc};
+
+use bitflags::bitflags;
+
+use crate::{
+    interrupt::{IntFlag, Interrupt},
+    memory::MemoryIO,
+};
+
+bitflags! {
+    /// The keys held down by the host. The lower nibble is the direction pad and the upper nibble the buttons, in the
+    /// order of the P10-P13 input lines.
+    pub struct Buttons: u8 {
+        const RIGHT = 0x01;
+        const LEFT = 0x02;
+        const UP = 0x04;
+        const DOWN = 0x08;
+        const A = 0x10;
+        const B = 0x20;
+        const SELECT = 0x40;
+        const START = 0x80;
+    }
+}
+
+/// P1/JOYP
+///
+/// The eight keys are arranged in a 2x4 matrix. The program selects either the direction keys (bit 4 = 0) or the
+/// button keys (bit 5 = 0), then reads their state from the lower nibble, where 0 means pressed.
+///
+/// - Bit 5 - Select button keys      (0=Select)
+/// - Bit 4 - Select direction keys   (0=Select)
+/// - Bit 3 - P13 Down  or Start      (0=Pressed) (Read Only)
+/// - Bit 2 - P12 Up    or Select     (0=Pressed) (Read Only)
+/// - Bit 1 - P11 Left  or B          (0=Pressed) (Read Only)
+/// - Bit 0 - P10 Right or A          (0=Pressed) (Read Only)
+pub struct Joypad {
+    select: u8,
+    buttons: Buttons,
+    interrupt: Rc<RefCell<Interrupt>>,
+}
+
+impl Joypad {
+    pub fn new(interrupt: Rc<RefCell<Interrupt>>) -> Self {
+        Self {
+            select: 0x30,
+            buttons: Buttons::empty(),
+            interrupt,
+        }
+    }
+
+    /// Update the keys held down by the host.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        let lines = self.lines();
+        self.buttons = buttons;
+        self.detect_falling_edge(lines);
+    }
+
+    /// The P10-P13 input lines, 0 for pressed.
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & 0x10 == 0 {
+            pressed |= self.buttons.bits() & 0x0f;
+        }
+        if self.select & 0x20 == 0 {
+            pressed |= self.buttons.bits() >> 4;
+        }
+        !pressed & 0x0f
+    }
+
+    /// The joypad interrupt is requested when any of the input lines goes from high to low.
+    fn detect_falling_edge(&mut self, old: u8) {
+        if old & !self.lines() != 0 {
+            self.interrupt
+                .borrow_mut()
+                .request_interrupt(IntFlag::JOYPAD);
+        }
+    }
+}
+
+impl MemoryIO for Joypad {
+    fn get8(&self, _: u16) -> u8 {
+        0xc0 | self.select | self.lines()
+    }
+
+    fn set8(&mut self, _: u16, n: u8) {
+        let lines = self.lines();
+        self.select = n & 0x30;
+        self.detect_falling_edge(lines);
+    }
+
+    fn get16(&self, _: u16) -> u16 {
+        unimplemented!("Joypad doesn't support reading 2-byte data.")
+    }
+
+    fn set16(&mut self, _: u16, _: u16) {
+        unimplemented!("Joypad doesn't support writing 2-byte data.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_button_press() {
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let mut joypad = Joypad::new(interrupt.clone());
+        // Select the button keys.
+        joypad.set8(0xff00, 0x10);
+        joypad.set_buttons(Buttons::UP);
+        assert_eq!(joypad.get8(0xff00), 0xdf);
+        assert_eq!(interrupt.borrow().request, 0);
+
+        joypad.set_buttons(Buttons::UP | Buttons::START);
+        assert_eq!(joypad.get8(0xff00), 0xd7);
+        assert_eq!(interrupt.borrow().request, IntFlag::JOYPAD.bits());
+    }
+}
@@ -0,0 +1,25 @@
+#![allow(clippy::new_without_default)]
+
+pub mod apu;
+pub mod camera;
+mod clock;
+mod cpu;
+pub mod gameboy;
+pub mod gpu;
+pub mod infrared;
+mod interrupt;
+pub mod joypad;
+pub mod link;
+pub mod mbc;
+mod memory;
+pub mod printer;
+pub mod serial;
+mod timer;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Term {
+    GB,  // Original GameBoy (GameBoy Classic)
+    GBP, // GameBoy Pocket/GameBoy Light
+    GBC, // GameBoy Color
+    SGB, // Super GameBoy
+}
@@ -0,0 +1,307 @@
+use std::{
+    cell::RefCell,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use crate::{
+    gameboy::{GameBoy, FRAME_CYCLES},
+    serial::{Serial, SerialSink},
+};
+
+/// A transfer started by the side with the internal clock, followed by its byte.
+const TRANSFER: u8 = 0x01;
+/// The answer of the other side, followed by the byte it shifted out.
+const REPLY: u8 = 0x02;
+
+/// Give up on a transfer if the partner doesn't answer in time, as if the cable was unplugged.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Link cable between two emulators over TCP.
+///
+/// The side with the internal clock (master) sends its byte and waits for the byte of the other side, so both see the
+/// same exchange. The other side (slave) answers from its polling: with its SB if it waits for a transfer with the
+/// external clock, with 0xff otherwise. If both sides happen to start a transfer with the internal clock at the same
+/// time, they answer each other with 0xff, as neither of them listens to the clock of the other.
+///
+/// Messages are 3 bytes: `TRANSFER` or `REPLY`, the sequence number of the transfer, then the data. A reply carries
+/// the number of the transfer it answers, so a late reply to a transfer which timed out is dropped instead of being
+/// taken for the answer to the next one.
+pub struct TcpLink {
+    stream: TcpStream,
+    /// Part of a message received so far.
+    pending: Vec<u8>,
+    /// Sequence number of the last transfer started by this side.
+    sequence: u8,
+}
+
+impl TcpLink {
+    /// Wait for the partner to connect.
+    pub fn listen(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        Self::from_stream(stream)
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::from_stream(TcpStream::connect(address)?)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        // Every message is tiny and waited for.
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(Self {
+            stream,
+            pending: Vec::with_capacity(3),
+            sequence: 0,
+        })
+    }
+
+    /// Another handle to the same connection, e.g. to plug it into a new machine after a reset.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let mut link = Self::from_stream(self.stream.try_clone()?)?;
+        // Replies to the transfers of this handle must not match the ones of the new handle.
+        link.sequence = self.sequence;
+        Ok(link)
+    }
+
+    /// Read one message, waiting for it if `blocking`.
+    fn receive(&mut self, blocking: bool) -> io::Result<Option<[u8; 3]>> {
+        self.stream.set_nonblocking(!blocking)?;
+        while self.pending.len() < 3 {
+            let mut buf = [0; 3];
+            let n = match self.stream.read(&mut buf[..3 - self.pending.len()]) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock && !blocking => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            self.pending.extend_from_slice(&buf[..n]);
+        }
+        let message = [self.pending[0], self.pending[1], self.pending[2]];
+        self.pending.clear();
+        Ok(Some(message))
+    }
+
+    fn send(&mut self, kind: u8, sequence: u8, byte: u8) -> io::Result<()> {
+        self.stream.set_nonblocking(false)?;
+        self.stream.write_all(&[kind, sequence, byte])
+    }
+}
+
+impl SerialSink for TcpLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.sequence = self.sequence.wrapping_add(1);
+        let sequence = self.sequence;
+        let mut exchange = || -> io::Result<u8> {
+            self.send(TRANSFER, sequence, byte)?;
+            loop {
+                match self.receive(true)? {
+                    Some([REPLY, n, reply]) if n == sequence => return Ok(reply),
+                    // Both sides drive the clock.
+                    Some([TRANSFER, n, _]) => self.send(REPLY, n, 0xff)?,
+                    // A late reply to a transfer which timed out.
+                    _ => (),
+                }
+            }
+        };
+        // Nobody on the other end.
+        exchange().unwrap_or(0xff)
+    }
+
+    fn poll(&mut self, byte: Option<u8>) -> Option<u8> {
+        match self.receive(false) {
+            Ok(Some([TRANSFER, sequence, incoming])) => {
+                self.send(REPLY, sequence, byte.unwrap_or(0xff)).ok()?;
+                byte.map(|_| incoming)
+            }
+            // A late reply to a transfer which timed out.
+            _ => None,
+        }
+    }
+}
+
+/// Clock cycles both machines of a `LinkedPair` run before the other one catches up. A bit takes 16 cycles with the
+/// fast CGB clock, so every clock edge reaches the partner in the slice it happens in.
+const SLICE: u64 = 16;
+
+/// Link cable between two machines of the same process, plugged straight into the serial port of the partner. Every
+/// bit is shifted into the partner on the clock edge of the master.
+struct Wire(Weak<RefCell<Serial>>);
+
+impl SerialSink for Wire {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.0
+            .upgrade()
+            .and_then(|partner| partner.borrow_mut().clock_in(byte))
+            .unwrap_or(0xff)
+    }
+
+    fn bitwise(&self) -> bool {
+        true
+    }
+
+    fn shift(&mut self, bit: bool) -> bool {
+        self.0
+            .upgrade()
+            .and_then(|partner| partner.borrow_mut().shift_in(bit))
+            .unwrap_or(true)
+    }
+}
+
+/// Two machines connected by a link cable and run in lockstep, e.g. for tests or a two-player view.
+///
+/// Both machines run the same slices of `SLICE` clock cycles in turn, so they never drift apart by more than a slice
+/// and an instruction, and a run is fully reproducible.
+pub struct LinkedPair {
+    pub gameboys: [GameBoy; 2],
+    /// Clock cycles run by each machine.
+    cycles: [u64; 2],
+}
+
+impl LinkedPair {
+    pub fn new(mut a: GameBoy, mut b: GameBoy) -> Self {
+        // Weak references, the serial ports would own each other otherwise.
+        let (serial_a, serial_b) = (a.serial(), b.serial());
+        a.set_serial_sink(Box::new(Wire(Rc::downgrade(&serial_b))));
+        b.set_serial_sink(Box::new(Wire(Rc::downgrade(&serial_a))));
+        Self {
+            gameboys: [a, b],
+            cycles: [0; 2],
+        }
+    }
+
+    /// Run both machines to the end of the next slice, one after the other.
+    pub fn step(&mut self) {
+        let end = (self.cycles[0].min(self.cycles[1]) / SLICE + 1) * SLICE;
+        for (gameboy, cycles) in self.gameboys.iter_mut().zip(self.cycles.iter_mut()) {
+            while *cycles < end {
+                *cycles += u64::from(gameboy.step());
+            }
+        }
+    }
+
+    /// Keep stepping until both machines have finished the current frame.
+    pub fn run_frame(&mut self) {
+        let frame = u64::from(FRAME_CYCLES);
+        let end = (self.cycles[0].min(self.cycles[1]) / frame + 1) * frame;
+        while self.cycles[0].min(self.cycles[1]) < end {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, thread};
+
+    use super::*;
+    use crate::{
+        interrupt::{IntFlag, Interrupt},
+        mbc::Cartridge,
+        memory::MemoryIO,
+        Term,
+    };
+
+    /// Run a transfer on one side, returns SB once the serial interrupt is requested.
+    fn exchange(link: TcpLink, sb: u8, sc: u8) -> u8 {
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let mut serial = Serial::new(Term::GB, interrupt.clone());
+        serial.set_sink(Some(Box::new(link)));
+        serial.set8(0xff01, sb);
+        serial.set8(0xff02, sc);
+        for _ in 0..100_000 {
+            serial.tick(512);
+            if interrupt.borrow().request & IntFlag::SERIAL.bits() != 0 {
+                return serial.get8(0xff01);
+            }
+            thread::sleep(Duration::from_micros(10));
+        }
+        panic!("the transfer never finished");
+    }
+
+    #[test]
+    fn test_tcp_link() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let slave = thread::spawn(move || {
+            let link = TcpLink::connect(address).unwrap();
+            exchange(link, 0x42, 0x80)
+        });
+        let link = TcpLink::from_stream(listener.accept().unwrap().0).unwrap();
+        assert_eq!(exchange(link, 0x99, 0x81), 0x42);
+        assert_eq!(slave.join().unwrap(), 0x99);
+    }
+
+    #[test]
+    fn test_tcp_link_late_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // A peer which answers the first transfer only after it timed out.
+        let peer = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut message = [0; 3];
+            stream.read_exact(&mut message).unwrap();
+            thread::sleep(TIMEOUT + Duration::from_millis(200));
+            stream.write_all(&[REPLY, message[1], 0x11]).unwrap();
+            stream.read_exact(&mut message).unwrap();
+            assert_eq!([message[0], message[2]], [TRANSFER, 0x02]);
+            stream.write_all(&[REPLY, message[1], 0x22]).unwrap();
+        });
+        let mut link = TcpLink::from_stream(listener.accept().unwrap().0).unwrap();
+        assert_eq!(link.transfer(0x01), 0xff);
+        assert_eq!(link.transfer(0x02), 0x22);
+        peer.join().unwrap();
+    }
+
+    /// A ROM which writes `sb` then `sc`, after `delay` NOPs, then loops forever.
+    fn transfer_rom(delay: usize, sb: u8, sc: u8) -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        let program = [0x3e, sb, 0xe0, 0x01, 0x3e, sc, 0xe0, 0x02, 0x18, 0xfe];
+        rom[0x100 + delay..0x100 + delay + program.len()].copy_from_slice(&program);
+        GameBoy::from_cartridge(Cartridge::try_from(rom).unwrap())
+    }
+
+    #[test]
+    fn test_linked_pair() {
+        // The master starts later, once the slave waits for the clock.
+        let master = transfer_rom(16, 0x99, 0x81);
+        let slave = transfer_rom(0, 0x42, 0x80);
+        let mut pair = LinkedPair::new(master, slave);
+        pair.run_frame();
+        let [master, slave] = &pair.gameboys;
+        assert_eq!(master.serial().borrow().get8(0xff01), 0x42);
+        assert_eq!(slave.serial().borrow().get8(0xff01), 0x99);
+        assert_eq!(slave.serial().borrow().get8(0xff02), 0x7e);
+    }
+
+    #[test]
+    fn test_wire() {
+        let interrupts = [(); 2].map(|_| Rc::new(RefCell::new(Interrupt::new())));
+        let [master, slave] = interrupts
+            .clone()
+            .map(|interrupt| Rc::new(RefCell::new(Serial::new(Term::GB, interrupt))));
+        master
+            .borrow_mut()
+            .set_sink(Some(Box::new(Wire(Rc::downgrade(&slave)))));
+        slave.borrow_mut().set8(0xff01, 0x42);
+        slave.borrow_mut().set8(0xff02, 0x80);
+        master.borrow_mut().set8(0xff01, 0x99);
+        master.borrow_mut().set8(0xff02, 0x81);
+
+        // Half the bits after half the byte time, on both sides.
+        master.borrow_mut().tick(512 * 4);
+        assert_eq!(master.borrow().get8(0xff01), 0x94);
+        assert_eq!(slave.borrow().get8(0xff01), 0x29);
+        assert_eq!(interrupts[1].borrow().request, 0);
+
+        master.borrow_mut().tick(512 * 4);
+        assert_eq!(master.borrow().get8(0xff01), 0x42);
+        assert_eq!(slave.borrow().get8(0xff01), 0x99);
+        for interrupt in interrupts {
+            assert_eq!(interrupt.borrow().request, IntFlag::SERIAL.bits());
+        }
+    }
+}
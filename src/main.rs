@@ -1,56 +1,372 @@
-mod cpu;
-mod gameboy;
-mod gpu;
-mod interrupt;
-mod mbc;
-mod memory;
+use std::path::PathBuf;
 
 use eframe::egui;
+use gb_emulator::{
+    camera::ImageSequence,
+    gameboy::{self, GameBoy},
+    gpu::{SCREEN_H, SCREEN_W},
+    joypad::Buttons,
+    link::TcpLink,
+    mbc::{self, Cartridge},
+    printer::Printer,
+    serial::SerialSink,
+};
 
-#[derive(Clone, Copy, Eq, PartialEq)]
-pub enum Term {
-    GB,  // Original GameBoy (GameBoy Classic)
-    GBP, // GameBoy Pocket/GameBoy Light
-    GBC, // GameBoy Color
-    SGB, // Super GameBoy
-}
+mod audio;
+mod pacer;
+
+use pacer::Pacer;
 
+/// Keyboard layout of the joypad.
+const KEYS: [(egui::Key, Buttons); 8] = [
+    (egui::Key::ArrowRight, Buttons::RIGHT),
+    (egui::Key::ArrowLeft, Buttons::LEFT),
+    (egui::Key::ArrowUp, Buttons::UP),
+    (egui::Key::ArrowDown, Buttons::DOWN),
+    (egui::Key::X, Buttons::A),
+    (egui::Key::Z, Buttons::B),
+    (egui::Key::Backspace, Buttons::SELECT),
+    (egui::Key::Enter, Buttons::START),
+];
+
+/// gb-emulator [rom] [--listen ADDRESS | --connect ADDRESS | --printer DIRECTORY] [--camera PNG | --camera DIRECTORY]
 fn main() {
-    let options = eframe::NativeOptions::default();
+    let mut path = None;
+    let mut accessory = None;
+    let mut camera = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" | "--connect" => {
+                let address = args.next().expect("Missing link cable address");
+                let result = if arg == "--listen" {
+                    println!("Waiting for the other player on {}", address);
+                    TcpLink::listen(address)
+                } else {
+                    TcpLink::connect(address)
+                };
+                accessory = Some(Accessory::Link(
+                    result.expect("Failed to connect the link cable"),
+                ));
+            }
+            "--printer" => {
+                let directory = args.next().expect("Missing printout directory");
+                accessory = Some(Accessory::Printer(Printer::new(PathBuf::from(directory))));
+            }
+            "--camera" => {
+                let source = PathBuf::from(args.next().expect("Missing camera picture"));
+                camera =
+                    Some(ImageSequence::open(&source).expect("Failed to load the camera picture"));
+            }
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let options = eframe::NativeOptions {
+        initial_window_size: Some(egui::vec2(
+            SCREEN_W as f32 * 3.0,
+            SCREEN_H as f32 * 3.0 + 24.0,
+        )),
+        ..Default::default()
+    };
     eframe::run_native(
-        "My egui App",
+        "GameBoy",
         options,
-        Box::new(|_cc| Box::new(MyApp::default())),
+        Box::new(|_cc| {
+            let mut app = App::new(accessory, camera);
+            if let Some(path) = path {
+                app.open(path);
+            }
+            Box::new(app)
+        }),
     );
 }
 
-struct MyApp {
-    name: String,
-    age: u32,
+struct App {
+    gameboy: Option<GameBoy>,
+    /// The ROM file, reloaded on reset.
+    path: Option<PathBuf>,
+    title: String,
+    /// The window title has to be updated on the next frame.
+    title_changed: bool,
+    error: Option<String>,
+    paused: bool,
+    pacer: Pacer,
+    screen: Option<egui::TextureHandle>,
+    /// Plugged into the link port of every machine.
+    accessory: Option<Accessory>,
+    /// The rumble motor of the cartridge ran during the last frame.
+    rumble: bool,
+    /// Where the screen was drawn on the last frame, to tilt the machine with the mouse.
+    screen_rect: Option<egui::Rect>,
+    /// What the Game Boy Camera sees, one picture per frame.
+    camera: Option<ImageSequence>,
+}
+
+/// What is at the other end of the link cable.
+enum Accessory {
+    /// Another emulator.
+    Link(TcpLink),
+    /// A Game Boy Printer, saving the printouts in a directory.
+    Printer(Printer),
 }
 
-impl Default for MyApp {
-    fn default() -> Self {
+impl Accessory {
+    fn plug(&self) -> std::io::Result<Box<dyn SerialSink>> {
+        Ok(match self {
+            Accessory::Link(link) => Box::new(link.try_clone()?),
+            Accessory::Printer(printer) => Box::new(printer.clone()),
+        })
+    }
+}
+
+impl App {
+    fn new(accessory: Option<Accessory>, camera: Option<ImageSequence>) -> Self {
         Self {
-            name: "Arthur".to_owned(),
-            age: 42,
+            gameboy: None,
+            path: None,
+            title: String::new(),
+            title_changed: false,
+            error: None,
+            paused: false,
+            pacer: Pacer::new(),
+            screen: None,
+            accessory,
+            rumble: false,
+            screen_rect: None,
+            camera,
         }
     }
-}
 
-impl eframe::App for MyApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("My egui Application");
-            ui.horizontal(|ui| {
-                ui.label("Your name: ");
-                ui.text_edit_singleline(&mut self.name);
+    /// Load a ROM and power on a new machine around it.
+    fn open(&mut self, path: PathBuf) {
+        self.error = None;
+        match Cartridge::new(path.clone()) {
+            Ok(cartridge) => {
+                // Before the new machine reads the save file, which may be the same.
+                self.close();
+                self.title = title(&cartridge);
+                self.title_changed = true;
+                let mut gameboy = GameBoy::from_cartridge(cartridge);
+                if let Err(e) = gameboy.attach_save(gameboy::save_path(&path)) {
+                    self.error = Some(format!("Save file: {}", e));
+                }
+                if let Some(accessory) = self.accessory.as_ref() {
+                    match accessory.plug() {
+                        Ok(sink) => gameboy.set_serial_sink(sink),
+                        Err(e) => self.error = Some(format!("Link cable: {}", e)),
+                    }
+                }
+                self.gameboy = Some(gameboy);
+                self.path = Some(path);
+                self.pacer.resync();
+            }
+            Err(e) => self.error = Some(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Power off the current machine, writing its save file and what is left on the paper of the printer.
+    fn close(&mut self) {
+        if let Some(mut gameboy) = self.gameboy.take() {
+            if let Err(e) = gameboy.save() {
+                self.error = Some(format!("Save file: {}", e));
+            }
+        }
+        if let Some(Accessory::Printer(printer)) = self.accessory.as_ref() {
+            if let Err(e) = printer.flush() {
+                self.error = Some(format!("Printer: {}", e));
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        if let Some(path) = self.path.clone() {
+            self.open(path);
+        }
+    }
+
+    fn menu(&mut self, ui: &mut egui::Ui, frame: &mut eframe::Frame) {
+        egui::menu::bar(ui, |ui| {
+            ui.menu_button("File", |ui| {
+                if ui.button("Open ROM…").clicked() {
+                    ui.close_menu();
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("GameBoy ROM", &["gb", "gbc", "sgb"])
+                        .pick_file()
+                    {
+                        self.open(path);
+                    }
+                }
+                if ui.button("Quit").clicked() {
+                    frame.close();
+                }
+            });
+            ui.menu_button("Emulation", |ui| {
+                let enabled = self.gameboy.is_some();
+                if ui
+                    .add_enabled(enabled, egui::Checkbox::new(&mut self.paused, "Pause"))
+                    .clicked()
+                {
+                    self.pacer.resync();
+                    ui.close_menu();
+                }
+                if ui
+                    .add_enabled(enabled, egui::Button::new("Reset"))
+                    .clicked()
+                {
+                    self.reset();
+                    ui.close_menu();
+                }
             });
-            ui.add(egui::Slider::new(&mut self.age, 0..=120).text("age"));
-            if ui.button("Click each year").clicked() {
-                self.age += 1;
+            if self.rumble {
+                ui.label(egui::RichText::new("Rumble").strong());
             }
-            ui.label(format!("Hello '{}', age {}", self.name, self.age));
         });
     }
+
+    /// Upload the framebuffer into the screen texture.
+    fn upload_screen(&mut self, ctx: &egui::Context) {
+        let frame = match self.gameboy.as_ref() {
+            Some(gameboy) => gameboy.frame(),
+            None => [[[0xff; 3]; SCREEN_W]; SCREEN_H],
+        };
+        let pixels = frame
+            .iter()
+            .flatten()
+            .map(|[r, g, b]| egui::Color32::from_rgb(*r, *g, *b))
+            .collect();
+        let image = egui::ColorImage {
+            size: [SCREEN_W, SCREEN_H],
+            pixels,
+        };
+        // Nearest filtering keeps the pixels sharp once scaled up.
+        match self.screen.as_mut() {
+            Some(screen) => screen.set(image, egui::TextureFilter::Nearest),
+            None => {
+                self.screen = Some(ctx.load_texture("screen", image, egui::TextureFilter::Nearest))
+            }
+        }
+    }
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        if self.title_changed {
+            self.title_changed = false;
+            frame.set_window_title(&format!("{} - GameBoy", self.title));
+        }
+
+        if let Some(gameboy) = self.gameboy.as_mut() {
+            let input = ctx.input();
+            let buttons = KEYS
+                .iter()
+                .filter(|(key, _)| input.key_down(*key))
+                .fold(Buttons::empty(), |buttons, (_, button)| buttons | *button);
+            let (x, y) = tilt(&input, self.screen_rect);
+            drop(input);
+            gameboy.set_buttons(buttons);
+            gameboy.with_accessory(|accessory| {
+                if let mbc::Accessory::Accelerometer(accelerometer) = accessory {
+                    accelerometer.set_tilt(x, y);
+                }
+            });
+            if !self.paused {
+                // One picture of the sequence per emulated frame.
+                let camera = &mut self.camera;
+                self.pacer.run(gameboy, |gameboy| {
+                    if let Some(camera) = camera.as_mut() {
+                        gameboy.with_accessory(|accessory| {
+                            if let mbc::Accessory::Camera(sensor) = accessory {
+                                sensor.set_image(camera.next_image());
+                            }
+                        });
+                    }
+                });
+            }
+            if let Some(Accessory::Printer(printer)) = self.accessory.as_ref() {
+                if let Some(e) = printer.take_error() {
+                    self.error = Some(format!("Printer: {}", e));
+                }
+            }
+            // Pulses count as running, the motor stays in its last state otherwise.
+            let rumble = gameboy
+                .with_accessory(|accessory| match accessory {
+                    mbc::Accessory::Rumble(rumble) => rumble.take_events(),
+                    _ => Vec::new(),
+                })
+                .unwrap_or_default();
+            if !rumble.is_empty() {
+                self.rumble = rumble.contains(&true);
+            }
+            if let Err(e) = gameboy.flush() {
+                self.error = Some(format!("Save file: {}", e));
+            }
+        }
+        self.upload_screen(ctx);
+
+        egui::TopBottomPanel::top("menu").show(ctx, |ui| self.menu(ui, frame));
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(egui::Color32::BLACK))
+            .show(ctx, |ui| {
+                if let Some(error) = self.error.as_ref() {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                // Integer scaling only, so that every GameBoy pixel has the same size.
+                let available = ui.available_size();
+                let scale = (available.x / SCREEN_W as f32)
+                    .min(available.y / SCREEN_H as f32)
+                    .floor()
+                    .max(1.0);
+                let size = egui::vec2(SCREEN_W as f32 * scale, SCREEN_H as f32 * scale);
+                if let Some(screen) = self.screen.as_ref() {
+                    let response = ui.centered_and_justified(|ui| ui.image(screen, size));
+                    self.screen_rect = Some(response.inner.rect);
+                }
+            });
+        // Keep repainting, the pacer decides how many frames each repaint runs.
+        ctx.request_repaint();
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.close();
+        if let Some(error) = self.error.as_ref() {
+            eprintln!("{}", error);
+        }
+    }
+}
+
+/// Tilt for the accelerometer of MBC7 cartridges, in g: from the arrow keys if one is held, from the position of the
+/// mouse over the screen otherwise, 1 g at the edges.
+fn tilt(input: &egui::InputState, screen: Option<egui::Rect>) -> (f32, f32) {
+    let axis = |negative, positive| {
+        f32::from(u8::from(input.key_down(positive)))
+            - f32::from(u8::from(input.key_down(negative)))
+    };
+    let keys = (
+        axis(egui::Key::ArrowLeft, egui::Key::ArrowRight),
+        axis(egui::Key::ArrowUp, egui::Key::ArrowDown),
+    );
+    if keys != (0.0, 0.0) {
+        return keys;
+    }
+    match (screen, input.pointer.hover_pos()) {
+        (Some(screen), Some(pointer)) if screen.contains(pointer) => {
+            let offset = (pointer - screen.center()) / (screen.size() / 2.0);
+            (offset.x.clamp(-1.0, 1.0), offset.y.clamp(-1.0, 1.0))
+        }
+        _ => (0.0, 0.0),
+    }
+}
+
+/// The title in the cartridge header, up to the first NUL. Newer cartridges use the last bytes for the manufacturer
+/// code and the CGB flag, which are not printable.
+fn title(cartridge: &Cartridge) -> String {
+    cartridge
+        .header()
+        .title()
+        .iter()
+        .take_while(|c| c.is_ascii_graphic() || **c == b' ')
+        .map(|c| *c as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
 }
@@ -0,0 +1,130 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::{
+    camera::{SENSOR_H, SENSOR_W},
+    infrared::Infrared,
+};
+
+use super::{Accelerometer, InfraredPort, Rumble, Sensor, Speaker};
+
+/// Events kept for the host, the oldest are dropped beyond that.
+const MAX_EVENTS: usize = 256;
+
+/// Events of an accessory not taken by the host yet.
+pub(super) struct Events<T>(VecDeque<T>);
+
+impl<T> Events<T> {
+    fn new() -> Self {
+        Self(VecDeque::new())
+    }
+
+    fn push(&mut self, event: T) {
+        if self.0.len() == MAX_EVENTS {
+            self.0.pop_front();
+        }
+        self.0.push_back(event);
+    }
+
+    fn take(&mut self) -> Vec<T> {
+        self.0.drain(..).collect()
+    }
+}
+
+impl Rumble {
+    pub(super) fn new() -> Self {
+        Self {
+            on: false,
+            events: Events::new(),
+        }
+    }
+
+    pub(super) fn set(&mut self, on: bool) {
+        if on != self.on {
+            self.on = on;
+            self.events.push(on);
+        }
+    }
+
+    /// Take the changes of the motor since the last call, `true` when it starts. There can be many per frame.
+    pub fn take_events(&mut self) -> Vec<bool> {
+        self.events.take()
+    }
+}
+
+/// Bit 0 of the register is the LED when written, and reads 1 when the sensor sees light. The other bits read 0xc0.
+impl InfraredPort {
+    pub(super) fn new() -> Self {
+        Self { infrared: None }
+    }
+
+    /// Share the LED and sensor of the machine, see `GameBoy::set_infrared_sink`.
+    pub(crate) fn connect(&mut self, infrared: Rc<RefCell<Infrared>>) {
+        self.infrared = Some(infrared);
+    }
+
+    pub(super) fn get8(&self) -> u8 {
+        let light = self
+            .infrared
+            .as_ref()
+            .is_some_and(|infrared| infrared.borrow_mut().light());
+        0xc0 | u8::from(light)
+    }
+
+    pub(super) fn set8(&self, n: u8) {
+        if let Some(infrared) = self.infrared.as_ref() {
+            infrared.borrow_mut().set_led(n & 0x01 != 0);
+        }
+    }
+}
+
+impl Speaker {
+    pub(super) fn new() -> Self {
+        Self {
+            tones: Events::new(),
+        }
+    }
+
+    pub(super) fn play(&mut self, tone: u8) {
+        self.tones.push(tone);
+    }
+
+    /// Take the tones started since the last call.
+    pub fn take_tones(&mut self) -> Vec<u8> {
+        self.tones.take()
+    }
+}
+
+impl Accelerometer {
+    pub(super) fn new() -> Self {
+        Self { tilt: (0.0, 0.0) }
+    }
+
+    /// Tilt the machine. `x` and `y` are in g, positive when the right and the bottom of the screen go down.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    pub(super) fn tilt(&self) -> (f32, f32) {
+        self.tilt
+    }
+}
+
+impl Sensor {
+    pub(super) fn new() -> Self {
+        // Mid gray until the host shows something.
+        Self {
+            image: vec![0x80; SENSOR_W * SENSOR_H],
+        }
+    }
+
+    /// Show a picture to the sensor: `SENSOR_W` x `SENSOR_H` bytes of luminance, e.g. from an `ImageSequence`. It is
+    /// used by the next captures.
+    pub fn set_image(&mut self, image: &[u8]) {
+        let size = image.len().min(self.image.len());
+        self.image[..size].copy_from_slice(&image[..size]);
+    }
+
+    pub(super) fn image(&self) -> &[u8] {
+        &self.image
+    }
+}
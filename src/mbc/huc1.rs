@@ -0,0 +1,131 @@
+use crate::memory::MemoryIO;
+
+use super::{get16, ram_offset, read_rom, set16, Accessory, Cartridge, HuC1, InfraredPort, Mbc};
+
+impl HuC1 {
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            ir_mode: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            infrared: InfraredPort::new(),
+            rom_bank: Vec::new(),
+            ram_bank: vec![0; ram_size],
+        }
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+    }
+}
+
+/// In IR mode, 0xa000-0xbfff is the register of the infrared port.
+impl MemoryIO for HuC1 {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.rom_bank, 0, address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_number as usize, address),
+            0xa000..=0xbfff if self.ir_mode => self.infrared.get8(),
+            0xa000..=0xbfff => ram_offset(&self.ram_bank, self.ram_bank_number as usize, address)
+                .map_or(0xff, |i| self.ram_bank[i]),
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            // There is no RAM enable, any other value maps the RAM.
+            0x0000..=0x1fff => self.ir_mode = n & 0x0f == 0x0e,
+            0x2000..=0x3fff => {
+                self.rom_bank_number = match n & 0x3f {
+                    0x00 => 0x01,
+                    bank => bank,
+                }
+            }
+            0x4000..=0x5fff => self.ram_bank_number = n & 0x03,
+            0xa000..=0xbfff if self.ir_mode => self.infrared.set8(n),
+            0xa000..=0xbfff => {
+                if let Some(i) = ram_offset(&self.ram_bank, self.ram_bank_number as usize, address)
+                {
+                    self.ram_bank[i] = n;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get16(&self, address: u16) -> u16 {
+        get16(self, address)
+    }
+
+    fn set16(&mut self, address: u16, n: u16) {
+        set16(self, address, n)
+    }
+}
+
+/// The RAM can't be disabled, so it is only saved on exit.
+impl Mbc for HuC1 {
+    fn save(&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&save[..size]);
+    }
+
+    fn accessory(&mut self) -> Option<Accessory<'_>> {
+        Some(Accessory::Infrared {
+            port: &mut self.infrared,
+            speaker: None,
+        })
+    }
+}
+
+impl From<Cartridge> for HuC1 {
+    fn from(c: Cartridge) -> Self {
+        let mut huc1 = Self::new(c.header().ram_size());
+        huc1.load_rom(&c.content);
+        huc1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::infrared::{tests::Mirror, Infrared};
+
+    #[test]
+    fn test_huc1() {
+        let mut rom = vec![0; 64 * 0x4000];
+        for bank in 0..64 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x147] = 0xff;
+        rom[0x149] = 0x02;
+        let mut huc1 = HuC1::from(Cartridge::try_from(rom).unwrap());
+        let infrared = Rc::new(RefCell::new(Infrared::new()));
+        infrared
+            .borrow_mut()
+            .set_sink(Some(Box::new(Mirror(false))));
+        huc1.infrared.connect(infrared.clone());
+
+        // 6 bits of ROM bank, 0 maps bank 1.
+        huc1.set8(0x2000, 0x3f);
+        assert_eq!(huc1.get8(0x4000), 0x3f);
+        huc1.set8(0x2000, 0x40);
+        assert_eq!(huc1.get8(0x4000), 0x01);
+
+        huc1.set8(0xa000, 0x42);
+        huc1.set8(0x0000, 0x0e);
+        assert_eq!(huc1.get8(0xa000), 0xc0);
+        huc1.set8(0xa000, 0x01);
+        assert!(infrared.borrow().led());
+        assert_eq!(huc1.get8(0xa000), 0xc1);
+        // The LED is on the port, not in the RAM.
+        huc1.set8(0x0000, 0x0a);
+        assert_eq!(huc1.get8(0xa000), 0x42);
+    }
+}
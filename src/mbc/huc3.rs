@@ -0,0 +1,341 @@
+use crate::{cpu::CLOCK_FREQUENCY, memory::MemoryIO};
+
+use super::{
+    get16, ram_offset, read_rom, set16, system_time, Accessory, Cartridge, HuC3, InfraredPort, Mbc,
+    Speaker, WallClock,
+};
+
+/// What 0xa000-0xbfff maps, written to 0x0000-0x1fff.
+const RAM_READ: u8 = 0x00;
+const RAM: u8 = 0x0a;
+const COMMAND: u8 = 0x0b;
+const RESPONSE: u8 = 0x0c;
+const SEMAPHORE: u8 = 0x0d;
+const IR: u8 = 0x0e;
+
+/// Clock cycles in a minute, the resolution of the clock.
+const MINUTE: u32 = 60 * CLOCK_FREQUENCY;
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Nibbles of the clock memory: the time as read or written by the extended commands, then the tone and the alarm.
+const TIME: usize = 0x00;
+const TONE: usize = 0x27;
+const ALARM: usize = 0x58;
+
+/// Size of the clock appended to the save file, in the layout of SameBoy: the UNIX time of the start of the current
+/// minute as a 64-bit integer, then the minutes, the days, the alarm minutes and the alarm days as 16-bit integers,
+/// then the alarm enable byte, all little-endian.
+const TRAILER: usize = 17;
+
+/// The clock, the alarm and the speaker of the HuC3, behind a microcontroller driven by commands.
+///
+/// A command is written in the `COMMAND` mode: the upper nibble is the command, the lower one its argument. Its
+/// result is read in the `RESPONSE` mode, with the command in the upper nibble.
+///
+/// - 0x1 - Read the nibble at the address, then increment the address
+/// - 0x3 - Write the argument at the address, then increment the address
+/// - 0x4 - Set the lower nibble of the address
+/// - 0x5 - Set the upper nibble of the address
+/// - 0x6 - Extended command: 0x0 copies the clock to 0x00-0x06, 0x1 sets the clock from 0x00-0x06, 0x2 reads the
+///   status (1=Ready), 0xe plays the tone at 0x27
+///
+/// The time at 0x00-0x06 is the minute of the day on 3 nibbles then the day on 4 nibbles, least significant first.
+pub(super) struct HuC3Rtc {
+    /// Minute of the day, 0-1439.
+    minutes: u16,
+    days: u16,
+    /// Clock cycles into the current minute.
+    cycles: u32,
+    /// One nibble per byte.
+    memory: [u8; 0x100],
+    address: u8,
+    response: u8,
+    speaker: Speaker,
+    now: WallClock,
+}
+
+impl HuC3Rtc {
+    pub fn new() -> Self {
+        Self {
+            minutes: 0,
+            days: 0,
+            cycles: 0,
+            memory: [0; 0x100],
+            address: 0,
+            response: 0,
+            speaker: Speaker::new(),
+            now: Box::new(system_time),
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= MINUTE {
+            self.cycles -= MINUTE;
+            self.advance(1);
+        }
+    }
+
+    fn advance(&mut self, minutes: u64) {
+        let total = u64::from(self.minutes) + minutes;
+        self.minutes = (total % u64::from(MINUTES_PER_DAY)) as u16;
+        self.days = self
+            .days
+            .wrapping_add((total / u64::from(MINUTES_PER_DAY)) as u16);
+    }
+
+    fn command(&mut self, n: u8) {
+        let (command, argument) = (n >> 4 & 0x07, n & 0x0f);
+        let value = match command {
+            0x1 => {
+                let value = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+                value
+            }
+            0x3 => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+                argument
+            }
+            0x4 => {
+                self.address = (self.address & 0xf0) | argument;
+                argument
+            }
+            0x5 => {
+                self.address = (self.address & 0x0f) | argument << 4;
+                argument
+            }
+            0x6 => self.extended(argument),
+            _ => argument,
+        };
+        self.response = command << 4 | value;
+    }
+
+    fn extended(&mut self, argument: u8) -> u8 {
+        match argument {
+            0x0 => {
+                let time = u32::from(self.minutes) | u32::from(self.days) << 12;
+                self.set_nibbles(TIME, 7, time);
+            }
+            0x1 => {
+                let time = self.nibbles(TIME, 7);
+                self.minutes = (time & 0xfff) as u16 % MINUTES_PER_DAY;
+                self.days = (time >> 12) as u16;
+                self.cycles = 0;
+            }
+            // The commands are done right away.
+            0x2 => return 0x1,
+            0xe => {
+                self.speaker.play(self.memory[TONE]);
+            }
+            _ => (),
+        }
+        argument
+    }
+
+    /// `count` nibbles of the memory from `address`, least significant first.
+    fn nibbles(&self, address: usize, count: usize) -> u32 {
+        self.memory[address..address + count]
+            .iter()
+            .rev()
+            .fold(0, |n, nibble| n << 4 | u32::from(*nibble))
+    }
+
+    fn set_nibbles(&mut self, address: usize, count: usize, n: u32) {
+        for (i, nibble) in self.memory[address..address + count].iter_mut().enumerate() {
+            *nibble = (n >> (i * 4)) as u8 & 0x0f;
+        }
+    }
+
+    fn trailer(&self) -> Vec<u8> {
+        let start = (self.now)().saturating_sub(u64::from(self.cycles / CLOCK_FREQUENCY));
+        let mut trailer = Vec::with_capacity(TRAILER);
+        trailer.extend_from_slice(&start.to_le_bytes());
+        trailer.extend_from_slice(&self.minutes.to_le_bytes());
+        trailer.extend_from_slice(&self.days.to_le_bytes());
+        trailer.extend_from_slice(&(self.nibbles(ALARM, 3) as u16).to_le_bytes());
+        trailer.extend_from_slice(&(self.nibbles(ALARM + 3, 4) as u16).to_le_bytes());
+        trailer.push(self.memory[ALARM + 7] & 0x01);
+        trailer
+    }
+
+    fn load_trailer(&mut self, trailer: &[u8]) {
+        if trailer.len() != TRAILER {
+            return;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([trailer[i], trailer[i + 1]]);
+        let start = u64::from_le_bytes(trailer[0..8].try_into().unwrap());
+        self.minutes = u16_at(8) % MINUTES_PER_DAY;
+        self.days = u16_at(10);
+        self.set_nibbles(ALARM, 3, u32::from(u16_at(12)));
+        self.set_nibbles(ALARM + 3, 4, u32::from(u16_at(14)));
+        self.memory[ALARM + 7] = trailer[16] & 0x01;
+        // Catch up with the time spent while the emulator was closed.
+        let elapsed = (self.now)().saturating_sub(start);
+        self.advance(elapsed / 60);
+        self.cycles = (elapsed % 60) as u32 * CLOCK_FREQUENCY;
+    }
+}
+
+impl HuC3 {
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            mode: RAM_READ,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            flush: false,
+            infrared: InfraredPort::new(),
+            rtc: HuC3Rtc::new(),
+            rom_bank: Vec::new(),
+            ram_bank: vec![0; ram_size],
+        }
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+    }
+
+    /// Replace the host clock used to catch up the clock, e.g. in tests.
+    pub fn set_wall_clock(&mut self, now: WallClock) {
+        self.rtc.now = now;
+    }
+
+    fn set_mode(&mut self, n: u8) {
+        let mode = n & 0x0f;
+        self.flush |= self.mode == RAM && mode != RAM;
+        self.mode = mode;
+    }
+}
+
+impl MemoryIO for HuC3 {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.rom_bank, 0, address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_number as usize, address),
+            0xa000..=0xbfff => match self.mode {
+                RAM_READ | RAM => {
+                    ram_offset(&self.ram_bank, self.ram_bank_number as usize, address)
+                        .map_or(0xff, |i| self.ram_bank[i])
+                }
+                RESPONSE => self.rtc.response,
+                SEMAPHORE => 0x01,
+                // Like the HuC1.
+                IR => self.infrared.get8(),
+                _ => 0xff,
+            },
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            0x0000..=0x1fff => self.set_mode(n),
+            0x2000..=0x3fff => self.rom_bank_number = n & 0x7f,
+            0x4000..=0x5fff => self.ram_bank_number = n & 0x03,
+            0xa000..=0xbfff => match self.mode {
+                RAM => {
+                    if let Some(i) =
+                        ram_offset(&self.ram_bank, self.ram_bank_number as usize, address)
+                    {
+                        self.ram_bank[i] = n;
+                    }
+                }
+                COMMAND => self.rtc.command(n),
+                IR => self.infrared.set8(n),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn get16(&self, address: u16) -> u16 {
+        get16(self, address)
+    }
+
+    fn set16(&mut self, address: u16, n: u16) {
+        set16(self, address, n)
+    }
+}
+
+impl Mbc for HuC3 {
+    fn tick(&mut self, cycles: u32) {
+        self.rtc.tick(cycles);
+    }
+
+    /// The clock is appended to the RAM.
+    fn save(&self) -> Vec<u8> {
+        let mut save = self.ram_bank.clone();
+        save.extend(self.rtc.trailer());
+        save
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&save[..size]);
+        self.rtc.load_trailer(&save[size..]);
+    }
+
+    fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.flush)
+    }
+
+    fn accessory(&mut self) -> Option<Accessory<'_>> {
+        Some(Accessory::Infrared {
+            port: &mut self.infrared,
+            speaker: Some(&mut self.rtc.speaker),
+        })
+    }
+}
+
+impl From<Cartridge> for HuC3 {
+    fn from(c: Cartridge) -> Self {
+        let mut huc3 = Self::new(c.header().ram_size());
+        huc3.load_rom(&c.content);
+        huc3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_huc3_rtc() {
+        let mut huc3 = HuC3::new(0x8000);
+        let command = |huc3: &mut HuC3, n: u8| {
+            huc3.set8(0x0000, COMMAND);
+            huc3.set8(0xa000, n);
+            huc3.set8(0x0000, RESPONSE);
+            huc3.get8(0xa000)
+        };
+
+        // Day 2, 23:59.
+        command(&mut huc3, 0x40);
+        command(&mut huc3, 0x50);
+        for nibble in [0xf, 0x9, 0x5, 0x2, 0x0, 0x0, 0x0] {
+            command(&mut huc3, 0x30 | nibble);
+        }
+        assert_eq!(command(&mut huc3, 0x61), 0x61);
+        huc3.tick(MINUTE);
+        assert_eq!(command(&mut huc3, 0x62), 0x61);
+        command(&mut huc3, 0x60);
+        command(&mut huc3, 0x40);
+        let time: Vec<u8> = (0..7).map(|_| command(&mut huc3, 0x10)).collect();
+        assert_eq!(time, [0x10, 0x10, 0x10, 0x13, 0x10, 0x10, 0x10]);
+
+        // The tone at 0x27.
+        command(&mut huc3, 0x47);
+        command(&mut huc3, 0x52);
+        command(&mut huc3, 0x33);
+        command(&mut huc3, 0x6e);
+        assert_eq!(huc3.rtc.speaker.take_tones(), vec![0x03]);
+
+        // Back to the RAM.
+        huc3.set8(0x0000, RAM);
+        huc3.set8(0xa000, 0x42);
+        huc3.set8(0x0000, RAM_READ);
+        huc3.set8(0xa000, 0x00);
+        assert_eq!(huc3.get8(0xa000), 0x42);
+        assert!(huc3.take_flush());
+    }
+}
@@ -1,80 +1,101 @@
 use crate::memory::MemoryIO;
 
-use super::{BankingMode, Cartridge, CartridgeHeader, MBC1};
+use super::{
+    get16, ram_offset, read_rom, set16, BankingMode, Cartridge, CartridgeHeader, Mbc, MBC1,
+};
 
 impl MBC1 {
     pub fn new(header: CartridgeHeader) -> Self {
         Self {
             ram_enable: false,
+            flush: false,
             rom_bank_number: 1,
             ram_bank_number: 0,
             banking_mode: BankingMode::Simple,
+            multicart: false,
             rom_bank: Vec::with_capacity(header.rom_size()),
-            ram_bank: Vec::with_capacity(header.ram_size()),
+            ram_bank: vec![0; header.ram_size()],
         }
     }
 
-    fn load_rom(&mut self, rom: &Vec<u8>) {
-        self.rom_bank.clone_from(rom);
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+        // Multicarts are 1 MiB, made of 4 games of 256 KiB which each have their own header. The second game starts at
+        // bank 0x10 with the 4-bit wiring.
+        self.multicart = rom.len() == 0x100000 && rom[0x104..0x134] == rom[0x40104..0x40134];
     }
 
     fn set_ram_enable(&mut self, is_enabled: u8) {
-        match is_enabled {
-            0x00 => self.ram_enable = false,
-            0x0a => self.ram_enable = true,
-            _ => (),
-        }
+        let enable = is_enabled & 0x0f == 0x0a;
+        self.flush |= self.ram_enable && !enable;
+        self.ram_enable = enable;
     }
 
+    /// Bank 0 can't be selected, it reads bank 1 instead. Only the 5 bits are checked, so banks 0x20, 0x40 and 0x60
+    /// can't be mapped at 0x4000 either.
     fn set_rom_bank(&mut self, bank: u8) {
-        match bank & 0x1f {
-            0x00 | 0x01 => self.rom_bank_number = 0x01,
-            0x02..=0x1f => self.rom_bank_number = bank,
-            _ => (),
-        }
-        // match self.banking_mode {
-        //     BankingMode::Simple => (),
-        //     BankingMode::Advanced => self.rom_bank_number += (self.ram_bank_number & 0x03) << 5,
-        // }
+        self.rom_bank_number = match bank & 0x1f {
+            0x00 => 0x01,
+            bank => bank,
+        };
     }
 
     fn set_ram_bank(&mut self, bank: u8) {
-        match bank & 0x1f {
-            0x00..=0x03 => self.rom_bank_number = bank,
-            _ => (),
-        }
+        self.ram_bank_number = bank & 0x03;
     }
 
     fn set_banking_mode(&mut self, mode: u8) {
-        match mode {
-            0x00 => self.banking_mode = BankingMode::Simple,
-            0x01 => self.banking_mode = BankingMode::Advanced,
-            _ => (),
+        self.banking_mode = match mode & 0x01 {
+            0x00 => BankingMode::Simple,
+            _ => BankingMode::Advanced,
+        };
+    }
+
+    /// The upper bits of the ROM bank, from the RAM bank register.
+    fn upper_rom_bank(&self) -> usize {
+        let shift = if self.multicart { 4 } else { 5 };
+        (self.ram_bank_number as usize) << shift
+    }
+
+    /// The bank mapped at 0x0000-0x3fff: always bank 0 in simple mode, while the advanced mode also applies the upper
+    /// bits there.
+    fn rom_bank_0(&self) -> usize {
+        match self.banking_mode {
+            BankingMode::Simple => 0,
+            BankingMode::Advanced => self.upper_rom_bank(),
         }
     }
+
+    /// The bank mapped at 0x4000-0x7fff.
+    fn rom_bank_n(&self) -> usize {
+        let lower = if self.multicart {
+            self.rom_bank_number & 0x0f
+        } else {
+            self.rom_bank_number
+        };
+        self.upper_rom_bank() | lower as usize
+    }
+
+    /// Offset of `address` in the RAM, which is banked in advanced mode only.
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        if !self.ram_enable {
+            return None;
+        }
+        let bank = match self.banking_mode {
+            BankingMode::Simple => 0,
+            BankingMode::Advanced => self.ram_bank_number as usize,
+        };
+        ram_offset(&self.ram_bank, bank, address)
+    }
 }
 
 impl MemoryIO for MBC1 {
     fn get8(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x3fff => match self.banking_mode {
-                BankingMode::Simple => self.rom_bank[address as usize & 0x3fff],
-                BankingMode::Advanced => {
-                    self.rom_bank[address as usize & 0x3fff + (self.ram_bank_number as usize) << 19]
-                }
-            },
-            0x4000..=0x7fff => {
-                self.rom_bank[(address as usize & 0x3fff)
-                    + ((self.rom_bank_number as usize) << 14)
-                    + ((self.ram_bank_number as usize) << 19)]
-            }
-            0xa000..=0xbfff => match self.banking_mode {
-                BankingMode::Simple => self.ram_bank[address as usize & 0x1fff],
-                BankingMode::Advanced => {
-                    self.ram_bank[address as usize & 0x1fff + (self.ram_bank_number as usize) << 13]
-                }
-            },
-            _ => 0,
+            0x0000..=0x3fff => read_rom(&self.rom_bank, self.rom_bank_0(), address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_n(), address),
+            0xa000..=0xbfff => self.ram_address(address).map_or(0xff, |i| self.ram_bank[i]),
+            _ => 0xff,
         }
     }
 
@@ -84,68 +105,36 @@ impl MemoryIO for MBC1 {
             0x2000..=0x3fff => self.set_rom_bank(n),
             0x4000..=0x5fff => self.set_ram_bank(n),
             0x6000..=0x7fff => self.set_banking_mode(n),
-            0xa000..=0xbfff => match self.banking_mode {
-                BankingMode::Simple => self.ram_bank[address as usize & 0x1fff] = n,
-                BankingMode::Advanced => {
-                    self.ram_bank
-                        [address as usize & 0x1fff + (self.ram_bank_number as usize) << 13] = n
+            0xa000..=0xbfff => {
+                if let Some(i) = self.ram_address(address) {
+                    self.ram_bank[i] = n;
                 }
-            },
+            }
             _ => (),
         }
     }
 
     fn get16(&self, address: u16) -> u16 {
-        match address {
-            0x0000..=0x3fff => match self.banking_mode {
-                BankingMode::Simple => unsafe {
-                    let address = (address as usize & 0x3fff) as isize;
-                    *(self.rom_bank.as_ptr().offset(address) as *const u16)
-                },
-                BankingMode::Advanced => unsafe {
-                    let address = (address as usize
-                        & 0x3fff + (self.ram_bank_number as usize) << 19)
-                        as isize;
-                    *(self.rom_bank.as_ptr().offset(address) as *const u16)
-                },
-            },
-            0x4000..=0x7fff => unsafe {
-                let address =
-                    (address as usize & 0x3fff + (self.rom_bank_number as usize) << 14) as isize;
-                *(self.rom_bank.as_ptr().offset(address) as *const u16)
-            },
-            0xa000..=0xbfff => match self.banking_mode {
-                BankingMode::Simple => unsafe {
-                    let address = (address as usize & 0x1fff) as isize;
-                    *(self.ram_bank.as_ptr().offset(address) as *const u16)
-                },
-                BankingMode::Advanced => unsafe {
-                    let address = (address as usize
-                        & 0x1fff + (self.ram_bank_number as usize) << 13)
-                        as isize;
-                    *(self.ram_bank.as_ptr().offset(address) as *const u16)
-                },
-            },
-            _ => 0,
-        }
+        get16(self, address)
     }
 
     fn set16(&mut self, address: u16, n: u16) {
-        match address {
-            0xa000..=0xbfff => match self.banking_mode {
-                BankingMode::Simple => unsafe {
-                    let address = (address as usize & 0x1fff) as isize;
-                    *(self.ram_bank.as_mut_ptr().offset(address as isize) as *mut u16) = n
-                },
-                BankingMode::Advanced => unsafe {
-                    let address = (address as usize
-                        & 0x1fff + (self.ram_bank_number as usize) << 13)
-                        as isize;
-                    *(self.ram_bank.as_mut_ptr().offset(address as isize) as *mut u16) = n
-                },
-            },
-            _ => (),
-        }
+        set16(self, address, n)
+    }
+}
+
+impl Mbc for MBC1 {
+    fn save(&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&save[..size]);
+    }
+
+    fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.flush)
     }
 }
 
@@ -157,3 +146,60 @@ impl From<Cartridge> for MBC1 {
         mbc1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM of `banks` banks, each starting with its number.
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for bank in 0..banks {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x03;
+        rom
+    }
+
+    #[test]
+    fn test_large_rom() {
+        let mut mbc1 = MBC1::from(Cartridge::try_from(rom(128)).unwrap());
+        mbc1.set8(0x2000, 0x00);
+        assert_eq!(mbc1.get8(0x4000), 0x01);
+        mbc1.set8(0x2000, 0x12);
+        mbc1.set8(0x4000, 0x02);
+        assert_eq!(mbc1.get8(0x4000), 0x52);
+        assert_eq!(mbc1.get8(0x0000), 0x00);
+        // Bank 0x40 at 0x0000 in advanced mode, still 0x41 instead of 0x40 at 0x4000.
+        mbc1.set8(0x6000, 0x01);
+        mbc1.set8(0x2000, 0x20);
+        assert_eq!(mbc1.get8(0x0000), 0x40);
+        assert_eq!(mbc1.get8(0x4000), 0x41);
+        // A 16-bit read goes through the same banking as two 8-bit ones.
+        assert_eq!(mbc1.get16(0x4000), 0x0041);
+        assert_eq!(mbc1.get16(0x3fff), 0x4100);
+
+        // 4 banks of RAM in advanced mode only.
+        mbc1.set8(0x0000, 0x0a);
+        mbc1.set8(0xa000, 0x42);
+        mbc1.set8(0x6000, 0x00);
+        assert_eq!(mbc1.get8(0xa000), 0x00);
+        mbc1.set8(0x6000, 0x01);
+        assert_eq!(mbc1.get8(0xa000), 0x42);
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut rom = rom(64);
+        for game in 0..4 {
+            rom[game * 0x40000 + 0x104..game * 0x40000 + 0x134].copy_from_slice(&[0xce; 0x30]);
+        }
+        let mut mbc1 = MBC1::from(Cartridge::try_from(rom).unwrap());
+        mbc1.set8(0x4000, 0x01);
+        mbc1.set8(0x2000, 0x12);
+        assert_eq!(mbc1.get8(0x4000), 0x12);
+        mbc1.set8(0x6000, 0x01);
+        assert_eq!(mbc1.get8(0x0000), 0x10);
+    }
+}
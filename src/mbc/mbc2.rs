@@ -0,0 +1,132 @@
+use crate::memory::MemoryIO;
+
+use super::{get16, read_rom, set16, Cartridge, Mbc, MBC2};
+
+impl MBC2 {
+    pub fn new() -> Self {
+        Self {
+            ram_enable: false,
+            rom_bank_number: 1,
+            flush: false,
+            rom_bank: Vec::new(),
+            ram_bank: [0; 0x200],
+        }
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+    }
+
+    /// Both registers are at 0x0000-0x3fff, bit 8 of the address tells which one is written.
+    fn set_register(&mut self, address: u16, n: u8) {
+        if address & 0x0100 == 0 {
+            let enable = n & 0x0f == 0x0a;
+            self.flush |= self.ram_enable && !enable;
+            self.ram_enable = enable;
+        } else {
+            self.rom_bank_number = match n & 0x0f {
+                0x00 => 0x01,
+                bank => bank,
+            };
+        }
+    }
+}
+
+impl MemoryIO for MBC2 {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.rom_bank, 0, address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_number as usize, address),
+            // Only 9 address bits are wired, so the RAM is echoed across the whole area. The upper nibble is open.
+            0xa000..=0xbfff if self.ram_enable => 0xf0 | self.ram_bank[address as usize & 0x1ff],
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            0x0000..=0x3fff => self.set_register(address, n),
+            0xa000..=0xbfff if self.ram_enable => {
+                self.ram_bank[address as usize & 0x1ff] = n & 0x0f
+            }
+            _ => (),
+        }
+    }
+
+    fn get16(&self, address: u16) -> u16 {
+        get16(self, address)
+    }
+
+    fn set16(&mut self, address: u16, n: u16) {
+        set16(self, address, n)
+    }
+}
+
+/// The RAM is saved as 512 bytes, one half-byte in each.
+impl Mbc for MBC2 {
+    fn save(&self) -> Vec<u8> {
+        self.ram_bank.to_vec()
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        for (b, s) in self.ram_bank.iter_mut().zip(save) {
+            *b = s & 0x0f;
+        }
+    }
+
+    fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.flush)
+    }
+}
+
+impl From<Cartridge> for MBC2 {
+    fn from(c: Cartridge) -> Self {
+        let mut mbc2 = Self::new();
+        mbc2.load_rom(&c.content);
+        mbc2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbc2() {
+        let mut rom = vec![0; 0x40000];
+        for bank in 0..16 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x147] = 0x06;
+        let mut mbc2 = MBC2::from(Cartridge::try_from(rom.clone()).unwrap());
+
+        assert_eq!(mbc2.get8(0x4000), 1);
+        // Bit 8 clear: RAM enable, not the ROM bank.
+        mbc2.set8(0x2000, 0x05);
+        assert_eq!(mbc2.get8(0x4000), 1);
+        mbc2.set8(0x2100, 0x05);
+        assert_eq!(mbc2.get8(0x4000), 5);
+        mbc2.set8(0x2100, 0x00);
+        assert_eq!(mbc2.get8(0x4000), 1);
+
+        assert_eq!(mbc2.get8(0xa000), 0xff);
+        mbc2.set8(0x0000, 0x0a);
+        mbc2.set8(0xa001, 0x3c);
+        assert_eq!(mbc2.get8(0xa001), 0xfc);
+        assert_eq!(mbc2.get8(0xa201), 0xfc);
+        assert_eq!(mbc2.get8(0xbe01), 0xfc);
+        mbc2.set8(0x0000, 0x00);
+        assert_eq!(mbc2.get8(0xa001), 0xff);
+        assert!(mbc2.take_flush());
+        assert_eq!(mbc2.save()[1], 0x0c);
+
+        // Only MBC2+BATTERY is saved, and the save is restored as is.
+        assert!(Cartridge::try_from(rom.clone()).unwrap().header().battery());
+        rom[0x147] = 0x05;
+        assert!(!Cartridge::try_from(rom).unwrap().header().battery());
+        let mut reloaded = MBC2::new();
+        reloaded.load(&mbc2.save());
+        reloaded.set8(0x0000, 0x0a);
+        assert_eq!(reloaded.get8(0xa001), 0xfc);
+    }
+}
@@ -0,0 +1,419 @@
+use crate::{cpu::CLOCK_FREQUENCY, memory::MemoryIO};
+
+use super::{get16, ram_offset, read_rom, set16, system_time, Cartridge, Mbc, WallClock, MBC3};
+
+/// Seconds, minutes, hours, lower 8 bits of the day counter, then DH.
+const RTC_REGISTERS: usize = 5;
+const S: usize = 0;
+const M: usize = 1;
+const H: usize = 2;
+const DL: usize = 3;
+const DH: usize = 4;
+/// Bits of each register which are wired.
+const RTC_MASKS: [u8; RTC_REGISTERS] = [0x3f, 0x3f, 0x1f, 0xff, 0xc1];
+
+/// DH bit 6: the clock is stopped.
+const HALT: u8 = 0x40;
+/// DH bit 7: the day counter overflowed, stays set until it is cleared by the game.
+const CARRY: u8 = 0x80;
+
+/// Size of the clock appended to the save file: the registers and the latched registers as 32-bit integers, then the
+/// UNIX time at which it was saved as a 64-bit integer, all little-endian. Some emulators write a 32-bit time.
+const TRAILER: usize = 48;
+const SHORT_TRAILER: usize = 44;
+
+/// Real-time clock of the MBC3, driven by a 32768 Hz crystal.
+///
+/// The game never reads the running clock: writing 0x00 then 0x01 to 0x6000-0x7fff copies it into the latched
+/// registers, which are what 0xa000-0xbfff reads. Writes go to the running clock.
+///
+/// DH:
+/// - Bit 7 - Day counter carry
+/// - Bit 6 - Halt          (0=Active, 1=Stop timer)
+/// - Bit 0 - Bit 8 of the day counter
+///
+/// While the emulator runs, the clock follows the emulated time. The time spent with the emulator closed is caught
+/// up from the host clock when the save file is loaded.
+pub(super) struct Rtc {
+    registers: [u8; RTC_REGISTERS],
+    latched: [u8; RTC_REGISTERS],
+    /// Clock cycles into the current second.
+    cycles: u32,
+    now: WallClock,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; RTC_REGISTERS],
+            latched: [0; RTC_REGISTERS],
+            cycles: 0,
+            now: Box::new(system_time),
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.registers[DH] & HALT != 0 {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CLOCK_FREQUENCY {
+            self.cycles -= CLOCK_FREQUENCY;
+            self.advance();
+        }
+    }
+
+    /// One second later. A counter set out of range by the game keeps counting up to its highest value and wraps to 0
+    /// without carrying.
+    fn advance(&mut self) {
+        let r = &mut self.registers;
+        r[S] = (r[S] + 1) & RTC_MASKS[S];
+        if r[S] != 60 {
+            return;
+        }
+        r[S] = 0;
+        r[M] = (r[M] + 1) & RTC_MASKS[M];
+        if r[M] != 60 {
+            return;
+        }
+        r[M] = 0;
+        r[H] = (r[H] + 1) & RTC_MASKS[H];
+        if r[H] != 24 {
+            return;
+        }
+        r[H] = 0;
+        let (dl, overflow) = r[DL].overflowing_add(1);
+        r[DL] = dl;
+        if overflow {
+            if r[DH] & 0x01 != 0 {
+                r[DH] = (r[DH] & !0x01) | CARRY;
+            } else {
+                r[DH] |= 0x01;
+            }
+        }
+    }
+
+    fn in_range(&self) -> bool {
+        let r = &self.registers;
+        r[S] < 60 && r[M] < 60 && r[H] < 24
+    }
+
+    /// Catch up with `seconds` spent while the emulator was closed, as fast as possible.
+    fn fast_forward(&mut self, mut seconds: u64) {
+        if self.registers[DH] & HALT != 0 {
+            return;
+        }
+        // Counters set out of range don't carry, let them wrap the slow way first.
+        while seconds > 0 && !self.in_range() {
+            self.advance();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+        let r = &mut self.registers;
+        let days = u64::from(r[DL]) | (u64::from(r[DH] & 0x01) << 8);
+        let total = u64::from(r[S])
+            + u64::from(r[M]) * 60
+            + u64::from(r[H]) * 3600
+            + days * 86400
+            + seconds;
+        let days = total / 86400;
+        r[S] = (total % 60) as u8;
+        r[M] = (total / 60 % 60) as u8;
+        r[H] = (total / 3600 % 24) as u8;
+        r[DL] = days as u8;
+        r[DH] = (r[DH] & !0x01) | ((days >> 8) & 0x01) as u8;
+        if days >= 512 {
+            r[DH] |= CARRY;
+        }
+    }
+
+    fn trailer(&self) -> Vec<u8> {
+        let mut trailer = Vec::with_capacity(TRAILER);
+        for r in self.registers.iter().chain(self.latched.iter()) {
+            trailer.extend_from_slice(&u32::from(*r).to_le_bytes());
+        }
+        trailer.extend_from_slice(&(self.now)().to_le_bytes());
+        trailer
+    }
+
+    fn load_trailer(&mut self, trailer: &[u8]) {
+        let timestamp = match trailer.len() {
+            TRAILER => u64::from_le_bytes(trailer[40..48].try_into().unwrap()),
+            SHORT_TRAILER => u64::from(u32::from_le_bytes(trailer[40..44].try_into().unwrap())),
+            _ => return,
+        };
+        for (i, r) in trailer[..40].chunks(4).enumerate() {
+            let n = r[0] & RTC_MASKS[i % RTC_REGISTERS];
+            if i < RTC_REGISTERS {
+                self.registers[i] = n;
+            } else {
+                self.latched[i - RTC_REGISTERS] = n;
+            }
+        }
+        self.cycles = 0;
+        self.fast_forward((self.now)().saturating_sub(timestamp));
+    }
+
+    fn latch(&mut self) {
+        self.latched = self.registers;
+    }
+
+    fn get(&self, register: usize) -> u8 {
+        self.latched[register]
+    }
+
+    fn set(&mut self, register: usize, n: u8) {
+        let n = n & RTC_MASKS[register];
+        // Writing the seconds restarts the current second.
+        if register == S {
+            self.cycles = 0;
+        }
+        self.registers[register] = n;
+        self.latched[register] = n;
+    }
+}
+
+impl MBC3 {
+    pub fn new(rtc: bool, ram_size: usize) -> Self {
+        Self {
+            ram_enable: false,
+            flush: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            latch: 0xff,
+            rtc: if rtc { Some(Rtc::new()) } else { None },
+            rom_bank: Vec::new(),
+            ram_bank: vec![0; ram_size],
+        }
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+    }
+
+    /// Replace the host clock used to catch up the real-time clock, e.g. in tests.
+    pub fn set_wall_clock(&mut self, now: WallClock) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.now = now;
+        }
+    }
+
+    fn set_rom_bank(&mut self, bank: u8) {
+        self.rom_bank_number = match bank & 0x7f {
+            0x00 => 0x01,
+            bank => bank,
+        };
+    }
+
+    fn set_latch(&mut self, n: u8) {
+        if let (0x00, 0x01, Some(rtc)) = (self.latch, n, self.rtc.as_mut()) {
+            rtc.latch();
+        }
+        self.latch = n;
+    }
+
+    /// Offset of `address` in the selected RAM bank, `None` if there is no RAM there.
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        match self.ram_bank_number {
+            bank @ 0x00..=0x03 => ram_offset(&self.ram_bank, bank as usize, address),
+            _ => None,
+        }
+    }
+
+    fn rtc_register(&self) -> Option<usize> {
+        match self.ram_bank_number {
+            0x08..=0x0c if self.rtc.is_some() => Some(self.ram_bank_number as usize - 0x08),
+            _ => None,
+        }
+    }
+}
+
+impl MemoryIO for MBC3 {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.rom_bank, 0, address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_number as usize, address),
+            0xa000..=0xbfff if self.ram_enable => {
+                if let Some(i) = self.ram_address(address) {
+                    self.ram_bank[i]
+                } else if let (Some(register), Some(rtc)) = (self.rtc_register(), self.rtc.as_ref())
+                {
+                    rtc.get(register)
+                } else {
+                    0xff
+                }
+            }
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            // Also enables the access to the clock.
+            0x0000..=0x1fff => {
+                let enable = n & 0x0f == 0x0a;
+                self.flush |= self.ram_enable && !enable;
+                self.ram_enable = enable;
+            }
+            0x2000..=0x3fff => self.set_rom_bank(n),
+            0x4000..=0x5fff => self.ram_bank_number = n,
+            0x6000..=0x7fff => self.set_latch(n),
+            0xa000..=0xbfff if self.ram_enable => {
+                if let Some(i) = self.ram_address(address) {
+                    self.ram_bank[i] = n;
+                } else if let Some(register) = self.rtc_register() {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.set(register, n);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get16(&self, address: u16) -> u16 {
+        get16(self, address)
+    }
+
+    fn set16(&mut self, address: u16, n: u16) {
+        set16(self, address, n)
+    }
+}
+
+impl Mbc for MBC3 {
+    fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.tick(cycles);
+        }
+    }
+
+    /// The clock is appended to the RAM.
+    fn save(&self) -> Vec<u8> {
+        let mut save = self.ram_bank.clone();
+        if let Some(rtc) = self.rtc.as_ref() {
+            save.extend(rtc.trailer());
+        }
+        save
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&save[..size]);
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load_trailer(&save[size..]);
+        }
+    }
+
+    fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.flush)
+    }
+}
+
+impl From<Cartridge> for MBC3 {
+    fn from(c: Cartridge) -> Self {
+        let header = c.header();
+        let mut mbc3 = Self::new(
+            matches!(header.cartridge_type(), 0x0f | 0x10),
+            header.ram_size(),
+        );
+        mbc3.load_rom(&c.content);
+        mbc3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rtc() {
+        let mut mbc3 = MBC3::new(true, 0x8000);
+        mbc3.set8(0x0000, 0x0a);
+        let latch = |mbc3: &mut MBC3| {
+            mbc3.set8(0x6000, 0x00);
+            mbc3.set8(0x6000, 0x01);
+        };
+        let read = |mbc3: &mut MBC3, register: u8| {
+            mbc3.set8(0x4000, register);
+            mbc3.get8(0xa000)
+        };
+
+        // 23:59:59 on day 511.
+        for (register, n) in [
+            (0x08, 59),
+            (0x09, 59),
+            (0x0a, 23),
+            (0x0b, 0xff),
+            (0x0c, 0x01),
+        ] {
+            mbc3.set8(0x4000, register);
+            mbc3.set8(0xa000, n);
+        }
+        mbc3.tick(CLOCK_FREQUENCY);
+        // Not latched yet.
+        assert_eq!(read(&mut mbc3, 0x08), 59);
+        latch(&mut mbc3);
+        assert_eq!(read(&mut mbc3, 0x08), 0);
+        assert_eq!(read(&mut mbc3, 0x0a), 0);
+        assert_eq!(read(&mut mbc3, 0x0b), 0);
+        assert_eq!(read(&mut mbc3, 0x0c), CARRY);
+
+        // Halted.
+        mbc3.set8(0x4000, 0x0c);
+        mbc3.set8(0xa000, HALT);
+        mbc3.tick(CLOCK_FREQUENCY * 2);
+        latch(&mut mbc3);
+        assert_eq!(read(&mut mbc3, 0x08), 0);
+
+        // RAM banks are still there.
+        mbc3.set8(0x4000, 0x03);
+        mbc3.set8(0xa000, 0x42);
+        mbc3.set8(0x4000, 0x00);
+        assert_eq!(mbc3.get8(0xa000), 0x00);
+        assert_eq!(read(&mut mbc3, 0x03), 0x42);
+    }
+
+    #[test]
+    fn test_rtc_save() {
+        use std::{cell::Cell, rc::Rc};
+
+        let time = Rc::new(Cell::new(1_000_000));
+        let clock = |time: &Rc<Cell<u64>>| -> WallClock {
+            let time = time.clone();
+            Box::new(move || time.get())
+        };
+        let mut mbc3 = MBC3::new(true, 0x2000);
+        mbc3.set_wall_clock(clock(&time));
+        mbc3.set8(0x0000, 0x0a);
+        mbc3.set8(0xa000, 0x42);
+        // 1 day, 23:59:50.
+        for (register, n) in [(0x08, 50), (0x09, 59), (0x0a, 23), (0x0b, 1)] {
+            mbc3.set8(0x4000, register);
+            mbc3.set8(0xa000, n);
+        }
+        let save = mbc3.save();
+        assert_eq!(save.len(), 0x2000 + TRAILER);
+        assert_eq!(save[0x2000..0x2004], [50, 0, 0, 0]);
+        assert_eq!(save[0x2028..0x2030], 1_000_000u64.to_le_bytes());
+
+        // Closed for 10 seconds.
+        time.set(1_000_010);
+        let mut mbc3 = MBC3::new(true, 0x2000);
+        mbc3.set_wall_clock(clock(&time));
+        mbc3.load(&save);
+        mbc3.set8(0x0000, 0x0a);
+        assert_eq!(mbc3.get8(0xa000), 0x42);
+        mbc3.set8(0x6000, 0x00);
+        mbc3.set8(0x6000, 0x01);
+        let mut read = |register| {
+            mbc3.set8(0x4000, register);
+            mbc3.get8(0xa000)
+        };
+        assert_eq!(read(0x08), 0);
+        assert_eq!(read(0x09), 0);
+        assert_eq!(read(0x0a), 0);
+        assert_eq!(read(0x0b), 2);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::memory::MemoryIO;
+
+use super::{get16, ram_offset, read_rom, set16, Accessory, Cartridge, Mbc, Rumble, MBC5};
+
+impl MBC5 {
+    pub fn new(rumble: bool, ram_size: usize) -> Self {
+        Self {
+            ram_enable: false,
+            flush: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            rumble: if rumble { Some(Rumble::new()) } else { None },
+            rom_bank: Vec::new(),
+            ram_bank: vec![0; ram_size],
+        }
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+    }
+
+    /// On rumble cartridges bit 3 drives the motor instead of selecting a RAM bank.
+    fn set_ram_bank(&mut self, n: u8) {
+        match self.rumble.as_mut() {
+            Some(rumble) => {
+                self.ram_bank_number = n & 0x07;
+                rumble.set(n & 0x08 != 0);
+            }
+            None => self.ram_bank_number = n & 0x0f,
+        }
+    }
+
+    fn ram_address(&self, address: u16) -> Option<usize> {
+        if !self.ram_enable {
+            return None;
+        }
+        ram_offset(&self.ram_bank, self.ram_bank_number as usize, address)
+    }
+}
+
+impl MemoryIO for MBC5 {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.rom_bank, 0, address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_number as usize, address),
+            0xa000..=0xbfff => self.ram_address(address).map_or(0xff, |i| self.ram_bank[i]),
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            0x0000..=0x1fff => {
+                let enable = n == 0x0a;
+                self.flush |= self.ram_enable && !enable;
+                self.ram_enable = enable;
+            }
+            0x2000..=0x2fff => self.rom_bank_number = (self.rom_bank_number & 0x100) | u16::from(n),
+            0x3000..=0x3fff => {
+                self.rom_bank_number = (self.rom_bank_number & 0xff) | (u16::from(n & 0x01) << 8)
+            }
+            0x4000..=0x5fff => self.set_ram_bank(n),
+            0xa000..=0xbfff => {
+                if let Some(i) = self.ram_address(address) {
+                    self.ram_bank[i] = n;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get16(&self, address: u16) -> u16 {
+        get16(self, address)
+    }
+
+    fn set16(&mut self, address: u16, n: u16) {
+        set16(self, address, n)
+    }
+}
+
+impl Mbc for MBC5 {
+    fn save(&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&save[..size]);
+    }
+
+    fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.flush)
+    }
+
+    fn accessory(&mut self) -> Option<Accessory<'_>> {
+        self.rumble.as_mut().map(Accessory::Rumble)
+    }
+}
+
+impl From<Cartridge> for MBC5 {
+    fn from(c: Cartridge) -> Self {
+        let header = c.header();
+        let mut mbc5 = Self::new(
+            matches!(header.cartridge_type(), 0x1c..=0x1e),
+            header.ram_size(),
+        );
+        mbc5.load_rom(&c.content);
+        mbc5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbc5() {
+        let mut rom = vec![0; 0x800000];
+        for bank in 0..0x200 {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        rom[0x147] = 0x1e;
+        rom[0x149] = 0x03;
+        let mut mbc5 = MBC5::from(Cartridge::try_from(rom).unwrap());
+
+        mbc5.set8(0x2000, 0x00);
+        assert_eq!(mbc5.get16(0x4000), 0x0000);
+        mbc5.set8(0x3000, 0x01);
+        mbc5.set8(0x2000, 0x23);
+        assert_eq!(mbc5.get16(0x4000), 0x0123);
+
+        // Bit 3 is the motor, not a RAM bank.
+        mbc5.set8(0x0000, 0x0a);
+        mbc5.set8(0x4000, 0x01);
+        mbc5.set8(0xa000, 0x42);
+        mbc5.set8(0x4000, 0x09);
+        assert_eq!(mbc5.get8(0xa000), 0x42);
+        mbc5.set8(0x4000, 0x08);
+        mbc5.set8(0x4000, 0x00);
+        assert_eq!(
+            mbc5.rumble.as_mut().unwrap().take_events(),
+            vec![true, false]
+        );
+        assert!(mbc5.rumble.as_mut().unwrap().take_events().is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::memory::MemoryIO;
+
+use super::{get16, read_rom, set16, Accelerometer, Accessory, Cartridge, Mbc, MBC7};
+
+/// Accelerometer value when flat, and its change for 1 g.
+const ACCELEROMETER_CENTER: f32 = 0x81d0 as f32;
+const ACCELEROMETER_G: f32 = 0x70 as f32;
+
+/// Pins of the EEPROM at 0xa080-0xa08f.
+const CS: u8 = 0x80;
+const CLK: u8 = 0x40;
+const DI: u8 = 0x02;
+const DO: u8 = 0x01;
+
+/// 128 words of 16 bits.
+const EEPROM_WORDS: usize = 0x80;
+
+enum EepromState {
+    /// Waiting for a start bit.
+    Idle,
+    /// Shifting the 2-bit opcode and the 8-bit address in.
+    Command { bits: u16, count: u8 },
+    /// Shifting a word out, MSB first.
+    Read { data: u16, count: u8 },
+    /// Shifting a word in, for a single word or for all of them with `None`.
+    Write {
+        address: Option<u8>,
+        data: u16,
+        count: u8,
+    },
+}
+
+/// 93LC56 serial EEPROM, 256 bytes organised as 16-bit words.
+///
+/// The game drives CS, CLK and DI through 0xa080-0xa08f and reads DO there. While CS is high, DI is sampled on the
+/// rising edges of CLK: a start bit (1), a 2-bit opcode and an 8-bit address (the upper bit is ignored), then the
+/// data for writes.
+///
+/// - 10 - READ:  16 bits are shifted out of DO
+/// - 01 - WRITE: 16 bits are shifted in from DI
+/// - 11 - ERASE: the word is set to 0xffff
+/// - 00 - Upper 2 address bits: 11=EWEN, 00=EWDS, 10=ERAL, 01=WRAL (16 bits are shifted in)
+///
+/// Writes need EWEN first. They are done immediately, so DO always reads ready (1) afterwards.
+pub(super) struct Eeprom {
+    words: [u16; EEPROM_WORDS],
+    write_enable: bool,
+    pins: u8,
+    state: EepromState,
+    /// A write has been done since the last flush.
+    flush: bool,
+}
+
+impl Eeprom {
+    pub fn new() -> Self {
+        Self {
+            // Blank, like an erased EEPROM.
+            words: [0xffff; EEPROM_WORDS],
+            write_enable: false,
+            pins: DO,
+            state: EepromState::Idle,
+            flush: false,
+        }
+    }
+
+    fn set_pins(&mut self, n: u8) {
+        let rising = self.pins & CLK == 0 && n & CLK != 0;
+        self.pins = (self.pins & DO) | (n & (CS | CLK | DI));
+        if n & CS == 0 {
+            self.state = EepromState::Idle;
+            self.pins |= DO;
+        } else if rising {
+            self.clock(n & DI != 0);
+        }
+    }
+
+    fn clock(&mut self, di: bool) {
+        let bit = u16::from(di);
+        self.state = match std::mem::replace(&mut self.state, EepromState::Idle) {
+            EepromState::Idle if di => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } if count < 9 => EepromState::Command {
+                bits: bits << 1 | bit,
+                count: count + 1,
+            },
+            EepromState::Command { bits, .. } => self.execute(bits << 1 | bit),
+            EepromState::Read { data, count } => {
+                self.set_do(data & 0x8000 != 0);
+                if count > 1 {
+                    EepromState::Read {
+                        data: data << 1,
+                        count: count - 1,
+                    }
+                } else {
+                    EepromState::Idle
+                }
+            }
+            EepromState::Write {
+                address,
+                data,
+                count,
+            } => {
+                let data = data << 1 | bit;
+                if count < 15 {
+                    EepromState::Write {
+                        address,
+                        data,
+                        count: count + 1,
+                    }
+                } else {
+                    match address {
+                        Some(address) => self.write(address, data),
+                        None => {
+                            for address in 0..EEPROM_WORDS as u8 {
+                                self.write(address, data);
+                            }
+                        }
+                    }
+                    self.set_do(true);
+                    EepromState::Idle
+                }
+            }
+        };
+    }
+
+    /// `command` is the opcode then the address.
+    fn execute(&mut self, command: u16) -> EepromState {
+        let address = command as u8 & 0x7f;
+        match command >> 8 {
+            0b10 => {
+                // A dummy 0 comes first.
+                self.set_do(false);
+                EepromState::Read {
+                    data: self.words[address as usize],
+                    count: 16,
+                }
+            }
+            0b01 => EepromState::Write {
+                address: Some(address),
+                data: 0,
+                count: 0,
+            },
+            0b11 => {
+                self.write(address, 0xffff);
+                EepromState::Idle
+            }
+            _ => match command >> 6 & 0x03 {
+                0b11 => {
+                    self.write_enable = true;
+                    EepromState::Idle
+                }
+                0b00 => {
+                    self.write_enable = false;
+                    EepromState::Idle
+                }
+                0b10 => {
+                    for address in 0..EEPROM_WORDS as u8 {
+                        self.write(address, 0xffff);
+                    }
+                    EepromState::Idle
+                }
+                _ => EepromState::Write {
+                    address: None,
+                    data: 0,
+                    count: 0,
+                },
+            },
+        }
+    }
+
+    fn write(&mut self, address: u8, data: u16) {
+        if self.write_enable {
+            self.words[address as usize] = data;
+            self.flush = true;
+        }
+    }
+
+    fn set_do(&mut self, high: bool) {
+        self.pins = (self.pins & !DO) | u8::from(high);
+    }
+}
+
+impl MBC7 {
+    pub fn new() -> Self {
+        Self {
+            ram_enable: [false; 2],
+            rom_bank_number: 1,
+            accelerometer: Accelerometer::new(),
+            latched: (0x8000, 0x8000),
+            erased: false,
+            eeprom: Eeprom::new(),
+            rom_bank: Vec::new(),
+        }
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+    }
+
+    /// Writing 0x55 then 0xaa samples the accelerometer.
+    fn latch(&mut self) {
+        if !self.erased {
+            return;
+        }
+        self.erased = false;
+        let value =
+            |g: f32| (ACCELEROMETER_CENTER + ACCELEROMETER_G * g).clamp(0.0, 65535.0) as u16;
+        let (x, y) = self.accelerometer.tilt();
+        self.latched = (value(x), value(y));
+    }
+}
+
+/// 0xa000-0xafff, with bits 4-7 of the address selecting the register:
+///
+/// - 0 - Write 0x55 to erase the accelerometer values
+/// - 1 - Write 0xaa to latch the accelerometer values
+/// - 2, 3 - X, low then high byte
+/// - 4, 5 - Y, low then high byte
+/// - 6 - Reads 0x00
+/// - 8 - EEPROM pins
+impl MemoryIO for MBC7 {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.rom_bank, 0, address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_number as usize, address),
+            0xa000..=0xafff if self.ram_enable == [true; 2] => match address >> 4 & 0x0f {
+                0x2 => self.latched.0 as u8,
+                0x3 => (self.latched.0 >> 8) as u8,
+                0x4 => self.latched.1 as u8,
+                0x5 => (self.latched.1 >> 8) as u8,
+                0x6 => 0x00,
+                0x8 => self.eeprom.pins,
+                _ => 0xff,
+            },
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enable[0] = n == 0x0a,
+            0x2000..=0x3fff => self.rom_bank_number = n & 0x7f,
+            0x4000..=0x5fff => self.ram_enable[1] = n == 0x40,
+            0xa000..=0xafff if self.ram_enable == [true; 2] => match (address >> 4 & 0x0f, n) {
+                (0x0, 0x55) => {
+                    self.latched = (0x8000, 0x8000);
+                    self.erased = true;
+                }
+                (0x1, 0xaa) => self.latch(),
+                (0x8, _) => self.eeprom.set_pins(n),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn get16(&self, address: u16) -> u16 {
+        get16(self, address)
+    }
+
+    fn set16(&mut self, address: u16, n: u16) {
+        set16(self, address, n)
+    }
+}
+
+/// The EEPROM is saved as 256 bytes, little-endian words.
+impl Mbc for MBC7 {
+    fn save(&self) -> Vec<u8> {
+        self.eeprom
+            .words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        for (word, bytes) in self.eeprom.words.iter_mut().zip(save.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.eeprom.flush)
+    }
+
+    fn accessory(&mut self) -> Option<Accessory<'_>> {
+        Some(Accessory::Accelerometer(&mut self.accelerometer))
+    }
+}
+
+impl From<Cartridge> for MBC7 {
+    fn from(c: Cartridge) -> Self {
+        let mut mbc7 = Self::new();
+        mbc7.load_rom(&c.content);
+        mbc7
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock `bits` bits of `n` into the EEPROM, MSB first. Returns what DO read after each rising edge.
+    fn shift(mbc7: &mut MBC7, n: u32, bits: u32) -> u32 {
+        let mut out = 0;
+        for i in (0..bits).rev() {
+            let di = if n >> i & 1 != 0 { DI } else { 0 };
+            mbc7.set8(0xa080, CS | di);
+            mbc7.set8(0xa080, CS | CLK | di);
+            out = out << 1 | u32::from(mbc7.get8(0xa080) & DO);
+        }
+        out
+    }
+
+    #[test]
+    fn test_mbc7() {
+        let mut mbc7 = MBC7::new();
+        mbc7.set8(0x0000, 0x0a);
+        mbc7.set8(0x4000, 0x40);
+
+        mbc7.accelerometer.set_tilt(1.0, -0.5);
+        mbc7.set8(0xa000, 0x55);
+        mbc7.set8(0xa010, 0xaa);
+        assert_eq!(mbc7.get16(0xa020) as u8, 0x40);
+        assert_eq!(mbc7.get8(0xa030), 0x82);
+        assert_eq!(mbc7.get8(0xa040), 0x98);
+        assert_eq!(mbc7.get8(0xa050), 0x81);
+
+        // EWEN, WRITE 0x1234 at 0x05, then READ it back: a start bit, the opcode and the address.
+        shift(&mut mbc7, 0b100_1100_0000, 11);
+        mbc7.set8(0xa080, 0x00);
+        shift(&mut mbc7, 0b101_0000_0101, 11);
+        shift(&mut mbc7, 0x1234, 16);
+        mbc7.set8(0xa080, 0x00);
+        assert!(mbc7.take_flush());
+        shift(&mut mbc7, 0b110_0000_0101, 11);
+        assert_eq!(shift(&mut mbc7, 0, 16), 0x1234);
+        mbc7.set8(0xa080, 0x00);
+        assert_eq!(mbc7.save()[10..12], [0x34, 0x12]);
+    }
+}
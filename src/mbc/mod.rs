@@ -1,19 +1,38 @@
-use std::{io::Read, path::PathBuf};
+use std::{
+    cell::RefCell,
+    io::{self, ErrorKind, Read},
+    path::PathBuf,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use crate::{infrared::Infrared, memory::MemoryIO};
+
+use self::{accessory::Events, huc3::HuC3Rtc, mbc3::Rtc, mbc7::Eeprom};
+
+mod accessory;
+mod huc1;
+mod huc3;
 mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc7;
 mod nombc;
+mod pocket_camera;
 
 pub struct Cartridge {
     content: Vec<u8>,
 }
 
 impl Cartridge {
-    pub fn new(path: PathBuf) -> std::io::Result<Self> {
+    /// Read a ROM file. Fails with `InvalidData` if it is too short to hold a header.
+    pub fn new(path: PathBuf) -> io::Result<Self> {
         let mut file = std::fs::File::open(path)?;
         let size = file.metadata()?.len() as usize;
         let mut v = vec![0; size];
-        file.read(&mut v)?;
-        Ok(Self { content: v })
+        file.read_exact(&mut v)?;
+        Self::try_from(v)
     }
 
     pub fn header(&self) -> CartridgeHeader {
@@ -21,6 +40,116 @@ impl Cartridge {
             ch: self.content[0x100..=0x14f].try_into().unwrap(),
         }
     }
+
+    /// Select the memory bank controller from the cartridge type and move the ROM into it.
+    pub fn into_mbc(self) -> Box<dyn Mbc> {
+        match self.header().cartridge_type() {
+            0x00 | 0x08 | 0x09 => Box::new(NoMBC::from(self)),
+            0x01..=0x03 => Box::new(MBC1::from(self)),
+            0x05 | 0x06 => Box::new(MBC2::from(self)),
+            0x0f..=0x13 => Box::new(MBC3::from(self)),
+            0x19..=0x1e => Box::new(MBC5::from(self)),
+            0x22 => Box::new(MBC7::from(self)),
+            0xfc => Box::new(PocketCamera::from(self)),
+            0xfe => Box::new(HuC3::from(self)),
+            0xff => Box::new(HuC1::from(self)),
+            _ => Box::new(NoMBC::from(self)),
+        }
+    }
+}
+
+impl TryFrom<Vec<u8>> for Cartridge {
+    type Error = io::Error;
+
+    fn try_from(content: Vec<u8>) -> io::Result<Self> {
+        if content.len() < 0x150 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a ROM: too short for a cartridge header",
+            ));
+        }
+        Ok(Self { content })
+    }
+}
+
+/// A memory bank controller, with the ROM and RAM of the cartridge behind it.
+pub trait Mbc: MemoryIO {
+    /// Advance the hardware of the cartridge, e.g. a real-time clock, by `cycles` normal speed clock cycles.
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// The memory kept by the battery, in the layout of the `.sav` files of other emulators: the external RAM as is.
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the memory from a `.sav` file. Missing bytes are left alone, extra bytes are ignored.
+    fn load(&mut self, _save: &[u8]) {}
+
+    /// Whether the game has disabled the RAM since the last call. Games enable the RAM only while they access it, so
+    /// this is a good time to write the save file.
+    fn take_flush(&mut self) -> bool {
+        false
+    }
+
+    /// The hardware of the cartridge beyond its memory, if it has any.
+    fn accessory(&mut self) -> Option<Accessory<'_>> {
+        None
+    }
+}
+
+/// Hardware of a cartridge beyond its memory, for the host to drive. See `GameBoy::with_accessory`.
+pub enum Accessory<'a> {
+    /// MBC5 types 0x1c-0x1e.
+    Rumble(&'a mut Rumble),
+    /// HuC1 and HuC3, only the HuC3 has the speaker.
+    Infrared {
+        port: &'a mut InfraredPort,
+        speaker: Option<&'a mut Speaker>,
+    },
+    /// MBC7
+    Accelerometer(&'a mut Accelerometer),
+    /// Game Boy Camera
+    Camera(&'a mut Sensor),
+}
+
+/// The rumble motor, driven by the game with pulses to vary its strength.
+pub struct Rumble {
+    on: bool,
+    /// Changes of the motor not taken by the host yet.
+    events: Events<bool>,
+}
+
+/// The infrared LED and sensor of the cartridge, wired to the infrared port of the machine.
+pub struct InfraredPort {
+    infrared: Option<Rc<RefCell<Infrared>>>,
+}
+
+/// The speaker of the HuC3, which plays tones on command.
+pub struct Speaker {
+    /// Tones played since the host last took them.
+    tones: Events<u8>,
+}
+
+/// The 2-axis accelerometer of the MBC7.
+pub struct Accelerometer {
+    /// Host tilt in g, positive to the right and to the bottom.
+    tilt: (f32, f32),
+}
+
+/// The image sensor of the Game Boy Camera.
+pub struct Sensor {
+    /// What the sensor sees, one byte of luminance per pixel.
+    image: Vec<u8>,
+}
+
+/// Seconds since the UNIX epoch, as the host sees them. Used to keep the clock of the cartridge running while the
+/// emulator is closed.
+pub type WallClock = Box<dyn Fn() -> u64>;
+
+fn system_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
 }
 
 pub struct CartridgeHeader {
@@ -45,10 +174,7 @@ impl CartridgeHeader {
     }
 
     pub fn cgb_flag(&self) -> bool {
-        match self.ch[0x43] {
-            0x80 | 0xc0 => true,
-            _ => false,
-        }
+        matches!(self.ch[0x43], 0x80 | 0xc0)
     }
 
     pub fn new_licensee_code(&self) -> u16 {
@@ -56,16 +182,32 @@ impl CartridgeHeader {
     }
 
     pub fn sgb_flag(&self) -> bool {
-        match self.ch[0x46] {
-            0x03 => true,
-            _ => false,
-        }
+        self.ch[0x46] == 0x03
     }
 
     pub fn cartridge_type(&self) -> u8 {
         self.ch[0x47]
     }
 
+    /// Whether the memory of the cartridge is kept by a battery, and so has to be saved.
+    pub fn battery(&self) -> bool {
+        matches!(
+            self.cartridge_type(),
+            0x03 | 0x06
+                | 0x09
+                | 0x0d
+                | 0x0f
+                | 0x10
+                | 0x13
+                | 0x1b
+                | 0x1e
+                | 0x22
+                | 0xfc
+                | 0xfe
+                | 0xff
+        )
+    }
+
     pub fn rom_size(&self) -> usize {
         match self.ch[0x48] {
             0x00 => 0x8000,
@@ -114,6 +256,33 @@ impl CartridgeHeader {
     }
 }
 
+/// Read from a ROM of any size: `bank` is mapped at 0x4000-0x7fff, and the bank bits the ROM doesn't have wrap around.
+fn read_rom(rom: &[u8], bank: usize, address: u16) -> u8 {
+    rom.get((address as usize & 0x3fff | bank << 14) % rom.len().max(1))
+        .copied()
+        .unwrap_or(0xff)
+}
+
+/// Offset of `address` in the external RAM with `bank` mapped at 0xa000-0xbfff, wrapping around smaller RAMs. `None`
+/// if the cartridge has no RAM.
+fn ram_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        None
+    } else {
+        Some((address as usize & 0x1fff | bank << 13) % ram.len())
+    }
+}
+
+/// 16-bit reads of the mappers are two 8-bit ones, low byte first, so that they go through the same banking.
+fn get16(mbc: &(impl MemoryIO + ?Sized), address: u16) -> u16 {
+    u16::from(mbc.get8(address)) | (u16::from(mbc.get8(address.wrapping_add(1))) << 8)
+}
+
+fn set16(mbc: &mut (impl MemoryIO + ?Sized), address: u16, n: u16) {
+    mbc.set8(address, n as u8);
+    mbc.set8(address.wrapping_add(1), (n >> 8) as u8);
+}
+
 enum BankingMode {
     Simple,
     Advanced,
@@ -121,15 +290,136 @@ enum BankingMode {
 
 pub struct NoMBC {
     rom_bank: [u8; 0x8000],
-    ram_bank: [u8; 0x2000],
+    ram_bank: Vec<u8>,
 }
 
 pub struct MBC1 {
     ram_enable: bool,
+    /// The RAM has been disabled since the last flush.
+    flush: bool,
+    /// Lower 5 bits of the ROM bank.
     rom_bank_number: u8,
+    /// 2 bits, the RAM bank or the upper bits of the ROM bank.
     ram_bank_number: u8,
     banking_mode: BankingMode,
+    /// MBC1M: the upper bits are wired to bit 4 of the ROM bank instead of bit 5.
+    multicart: bool,
+
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+pub struct MBC2 {
+    ram_enable: bool,
+    rom_bank_number: u8,
+    /// The RAM has been disabled since the last flush.
+    flush: bool,
+
+    rom_bank: Vec<u8>,
+    /// 512 half-bytes, one per byte with only the lower nibble used.
+    ram_bank: [u8; 0x200],
+}
+
+pub struct MBC3 {
+    ram_enable: bool,
+    rom_bank_number: u8,
+    /// 0x00-0x03 selects a RAM bank, 0x08-0x0c an RTC register.
+    ram_bank_number: u8,
+    /// The RAM has been disabled since the last flush.
+    flush: bool,
+    /// Last value written to 0x6000-0x7fff, the clock is latched on a 0x00 to 0x01 transition.
+    latch: u8,
+    /// Only types 0x0f and 0x10 have the clock.
+    rtc: Option<Rtc>,
+
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+pub struct MBC5 {
+    ram_enable: bool,
+    /// The RAM has been disabled since the last flush.
+    flush: bool,
+    /// 9 bits, bank 0 can be mapped at 0x4000 too.
+    rom_bank_number: u16,
+    ram_bank_number: u8,
+    /// Only types 0x1c-0x1e have the motor.
+    rumble: Option<Rumble>,
+
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+pub struct HuC1 {
+    /// 0x0e maps the infrared port at 0xa000-0xbfff instead of the RAM.
+    ir_mode: bool,
+    rom_bank_number: u8,
+    ram_bank_number: u8,
+    infrared: InfraredPort,
 
     rom_bank: Vec<u8>,
     ram_bank: Vec<u8>,
 }
+
+pub struct HuC3 {
+    /// What 0xa000-0xbfff maps, selected by 0x0000-0x1fff.
+    mode: u8,
+    rom_bank_number: u8,
+    ram_bank_number: u8,
+    /// The RAM has been disabled since the last flush.
+    flush: bool,
+    infrared: InfraredPort,
+    rtc: HuC3Rtc,
+
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+pub struct MBC7 {
+    /// Both RAM enables are needed to access the registers at 0xa000-0xafff.
+    ram_enable: [bool; 2],
+    rom_bank_number: u8,
+    accelerometer: Accelerometer,
+    /// Accelerometer values as read by the game, 0x81d0 when flat.
+    latched: (u16, u16),
+    /// The latched values have been erased, ready for a new latch.
+    erased: bool,
+    eeprom: Eeprom,
+
+    rom_bank: Vec<u8>,
+}
+
+/// Game Boy Camera (Pocket Camera)
+pub struct PocketCamera {
+    ram_enable: bool,
+    /// The RAM has been disabled since the last flush.
+    flush: bool,
+    rom_bank_number: u8,
+    /// 0x00-0x0f selects a RAM bank, bit 4 maps the camera registers instead.
+    ram_bank_number: u8,
+    /// 0xa000-0xa035, see `pocket_camera`.
+    registers: [u8; 0x36],
+    /// Clock cycles until the running capture is done.
+    capture: Option<u32>,
+    sensor: Sensor,
+
+    rom_bank: Vec<u8>,
+    ram_bank: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_rom() {
+        let path = std::env::temp_dir().join(format!("gb-emulator-{}.gb", std::process::id()));
+        std::fs::write(&path, [0; 0x14f]).unwrap();
+        let err = Cartridge::new(path.clone()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        std::fs::write(&path, [0; 0x150]).unwrap();
+        assert!(Cartridge::new(path.clone()).is_ok());
+        std::fs::remove_file(&path).unwrap();
+        assert!(Cartridge::try_from(vec![0; 0x100]).is_err());
+    }
+}
@@ -1,17 +1,18 @@
 use crate::memory::MemoryIO;
 
-use super::{Cartridge, NoMBC};
+use super::{get16, set16, Cartridge, CartridgeHeader, Mbc, NoMBC};
 
 impl NoMBC {
-    pub fn new() -> Self {
+    pub fn new(header: CartridgeHeader) -> Self {
         Self {
             rom_bank: [0; 0x8000],
-            ram_bank: [0; 0x2000],
+            ram_bank: vec![0; header.ram_size()],
         }
     }
 
-    fn load_rom(&mut self, rom: &Vec<u8>) {
-        self.rom_bank.clone_from_slice(&rom[..]);
+    fn load_rom(&mut self, rom: &[u8]) {
+        let size = rom.len().min(self.rom_bank.len());
+        self.rom_bank[..size].copy_from_slice(&rom[..size]);
     }
 }
 
@@ -19,48 +20,49 @@ impl MemoryIO for NoMBC {
     fn get8(&self, address: u16) -> u8 {
         match address {
             0x0000..=0x7fff => self.rom_bank[address as usize],
-            0xa000..=0xbfff => self.ram_bank[address as usize],
+            0xa000..=0xbfff => self
+                .ram_bank
+                .get(address as usize - 0xa000)
+                .copied()
+                .unwrap_or(0xff),
             _ => 0,
         }
     }
 
     fn set8(&mut self, address: u16, n: u8) {
-        match address {
-            0x0000..=0x7fff => self.rom_bank[address as usize] = n,
-            0xa000..=0xbfff => self.ram_bank[address as usize] = n,
-            _ => (),
+        // ROM is read-only, only the external RAM can be written.
+        if let 0xa000..=0xbfff = address {
+            if let Some(b) = self.ram_bank.get_mut(address as usize - 0xa000) {
+                *b = n;
+            }
         }
     }
 
     fn get16(&self, address: u16) -> u16 {
-        match address {
-            0x0000..=0x7fff => unsafe {
-                *(self.rom_bank.as_ptr().offset(address as isize) as *const u16)
-            },
-            0xa000..=0xbfff => unsafe {
-                *(self.ram_bank.as_ptr().offset(address as isize) as *const u16)
-            },
-            _ => 0,
-        }
+        get16(self, address)
     }
 
     fn set16(&mut self, address: u16, n: u16) {
-        match address {
-            0x0000..=0x7fff => unsafe {
-                *(self.rom_bank.as_mut_ptr().offset(address as isize) as *mut u16) = n
-            },
-            0xa000..=0xbfff => unsafe {
-                *(self.ram_bank.as_mut_ptr().offset(address as isize) as *mut u16) = n
-            },
-            _ => (),
-        }
+        set16(self, address, n)
+    }
+}
+
+/// There is no register to disable the RAM, so it is only saved on exit.
+impl Mbc for NoMBC {
+    fn save(&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&save[..size]);
     }
 }
 
 impl From<Cartridge> for NoMBC {
     fn from(c: Cartridge) -> Self {
-        let mut mbc1 = Self::new();
-        mbc1.load_rom(&c.content);
-        mbc1
+        let mut nombc = Self::new(c.header());
+        nombc.load_rom(&c.content);
+        nombc
     }
 }
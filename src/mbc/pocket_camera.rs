@@ -0,0 +1,273 @@
+use crate::{
+    camera::{SENSOR_H, SENSOR_W},
+    memory::MemoryIO,
+};
+
+use super::{get16, ram_offset, read_rom, set16, Accessory, Cartridge, Mbc, PocketCamera, Sensor};
+
+/// Registers of the sensor (M64282FP) and of the mapper.
+const CONTROL: usize = 0x00;
+const MODE: usize = 0x01;
+const EXPOSURE: usize = 0x02;
+const EDGE: usize = 0x04;
+/// 4x4 matrix of 3 thresholds each, for dithering the picture to 4 shades.
+const DITHERING: usize = 0x06;
+
+/// A000 bit 0: start a capture, reads 1 until it is done.
+const CAPTURE: u8 = 0x01;
+/// A004 bit 3: invert the picture.
+const INVERT: u8 = 0x08;
+
+/// Edge enhancement ratios selected by A004 bits 4-6.
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// Where the captured picture is written in RAM bank 0, as 16x14 tiles in the 2bpp format of the GPU.
+const PICTURE: usize = 0x0100;
+
+impl PocketCamera {
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            ram_enable: false,
+            flush: false,
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            registers: [0; 0x36],
+            capture: None,
+            sensor: Sensor::new(),
+            rom_bank: Vec::new(),
+            ram_bank: vec![0; ram_size],
+        }
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) {
+        self.rom_bank = rom.to_vec();
+    }
+
+    fn set_register(&mut self, register: usize, n: u8) {
+        if register >= self.registers.len() {
+            return;
+        }
+        self.registers[register] = n;
+        if register == CONTROL && n & CAPTURE != 0 && self.capture.is_none() {
+            self.capture = Some(self.capture_cycles());
+        } else if register == CONTROL && n & CAPTURE == 0 {
+            self.capture = None;
+        }
+    }
+
+    fn exposure(&self) -> u32 {
+        u32::from(self.registers[EXPOSURE]) << 8 | u32::from(self.registers[EXPOSURE + 1])
+    }
+
+    /// The exposure time is counted in units of 16 M-cycles.
+    fn capture_cycles(&self) -> u32 {
+        let n = if self.registers[MODE] & 0x80 != 0 {
+            0
+        } else {
+            512
+        };
+        (32446 + n + 16 * self.exposure()) * 4
+    }
+
+    /// The pixel of the sensor, with the borders repeated.
+    fn sensor(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, SENSOR_W as isize - 1) as usize;
+        let y = y.clamp(0, SENSOR_H as isize - 1) as usize;
+        f32::from(self.sensor.image()[y * SENSOR_W + x])
+    }
+
+    /// A pixel after exposure and edge enhancement, 0-255. A001 bits 5-6 enable the enhancement horizontally and
+    /// vertically, A004 bits 4-6 select its strength. The gain and the voltages are not modelled.
+    fn processed(&self, x: usize, y: usize) -> u8 {
+        let (x, y) = (x as isize, y as isize);
+        let center = self.sensor(x, y);
+        let mut edge = 0.0;
+        if self.registers[MODE] & 0x20 != 0 {
+            edge += 2.0 * center - self.sensor(x - 1, y) - self.sensor(x + 1, y);
+        }
+        if self.registers[MODE] & 0x40 != 0 {
+            edge += 2.0 * center - self.sensor(x, y - 1) - self.sensor(x, y + 1);
+        }
+        let ratio = EDGE_RATIOS[(self.registers[EDGE] >> 4 & 0x07) as usize];
+        let mut value = (center + edge * ratio) * self.exposure() as f32 / 0x1000 as f32;
+        if self.registers[EDGE] & INVERT != 0 {
+            value = 255.0 - value;
+        }
+        value.clamp(0.0, 255.0) as u8
+    }
+
+    /// Dither the picture to 4 shades with the threshold matrix, and write it to RAM bank 0.
+    fn develop(&mut self) {
+        for y in 0..SENSOR_H {
+            for x in 0..SENSOR_W {
+                let thresholds = DITHERING + ((y & 3) * 4 + (x & 3)) * 3;
+                let value = self.processed(x, y);
+                let shade = self.registers[thresholds..thresholds + 3]
+                    .iter()
+                    .filter(|threshold| value < **threshold)
+                    .count() as u8;
+                let tile = (y / 8) * (SENSOR_W / 8) + x / 8;
+                let i = PICTURE + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                for plane in 0..2 {
+                    if let Some(b) = self.ram_bank.get_mut(i + plane) {
+                        if shade >> plane & 1 != 0 {
+                            *b |= bit;
+                        } else {
+                            *b &= !bit;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 0x4000-0x5fff with bit 4 set maps the registers at 0xa000-0xa07f, mirrored up to 0xbfff:
+///
+/// - A000    - Bit 0: start a capture, reads 1 while busy. Bits 1-2 are the output mode of the sensor
+/// - A001    - Bit 7: N, no exposure offset. Bits 5-6: edge enhancement (1=Horizontal, 2=Vertical). Bits 0-4: gain
+/// - A002-3  - Exposure time, high byte first, in units of 16 M-cycles. 0x1000 is neutral
+/// - A004    - Bits 4-6: edge enhancement ratio. Bit 3: invert. Bits 0-2: output reference voltage
+/// - A005    - Bits 6-7: zero point. Bits 0-5: output offset voltage
+/// - A006-35 - Dithering matrix: 3 increasing thresholds for each pixel of a 4x4 block. Darker than the first is
+///   black, than the second dark gray, than the third light gray, white otherwise
+///
+/// Only A000 can be read, the others read 0x00. The RAM can be read even when it is not enabled.
+impl MemoryIO for PocketCamera {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom(&self.rom_bank, 0, address),
+            0x4000..=0x7fff => read_rom(&self.rom_bank, self.rom_bank_number as usize, address),
+            0xa000..=0xbfff if self.ram_bank_number & 0x10 != 0 => match address & 0x7f {
+                0x00 => {
+                    (self.registers[CONTROL] & 0x06)
+                        | if self.capture.is_some() { CAPTURE } else { 0 }
+                }
+                _ => 0x00,
+            },
+            0xa000..=0xbfff => ram_offset(
+                &self.ram_bank,
+                self.ram_bank_number as usize & 0x0f,
+                address,
+            )
+            .map_or(0xff, |i| self.ram_bank[i]),
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            0x0000..=0x1fff => {
+                let enable = n & 0x0f == 0x0a;
+                self.flush |= self.ram_enable && !enable;
+                self.ram_enable = enable;
+            }
+            0x2000..=0x3fff => self.rom_bank_number = n & 0x3f,
+            0x4000..=0x5fff => self.ram_bank_number = n & 0x1f,
+            0xa000..=0xbfff if self.ram_bank_number & 0x10 != 0 => {
+                self.set_register(address as usize & 0x7f, n)
+            }
+            0xa000..=0xbfff if self.ram_enable => {
+                if let Some(i) = ram_offset(
+                    &self.ram_bank,
+                    self.ram_bank_number as usize & 0x0f,
+                    address,
+                ) {
+                    self.ram_bank[i] = n;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get16(&self, address: u16) -> u16 {
+        get16(self, address)
+    }
+
+    fn set16(&mut self, address: u16, n: u16) {
+        set16(self, address, n)
+    }
+}
+
+/// The album is in the RAM, saved as is.
+impl Mbc for PocketCamera {
+    fn tick(&mut self, cycles: u32) {
+        if let Some(remaining) = self.capture {
+            if remaining > cycles {
+                self.capture = Some(remaining - cycles);
+            } else {
+                self.capture = None;
+                self.registers[CONTROL] &= !CAPTURE;
+                self.develop();
+            }
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.ram_bank.clone()
+    }
+
+    fn load(&mut self, save: &[u8]) {
+        let size = save.len().min(self.ram_bank.len());
+        self.ram_bank[..size].copy_from_slice(&save[..size]);
+    }
+
+    fn take_flush(&mut self) -> bool {
+        std::mem::take(&mut self.flush)
+    }
+
+    fn accessory(&mut self) -> Option<Accessory<'_>> {
+        Some(Accessory::Camera(&mut self.sensor))
+    }
+}
+
+impl From<Cartridge> for PocketCamera {
+    fn from(c: Cartridge) -> Self {
+        let mut camera = Self::new(c.header().ram_size());
+        camera.load_rom(&c.content);
+        camera
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let mut camera = PocketCamera::new(0x20000);
+        // Black on the left half, white on the right half.
+        let image: Vec<u8> = (0..SENSOR_W * SENSOR_H)
+            .map(|i| {
+                if i % SENSOR_W < SENSOR_W / 2 {
+                    0x00
+                } else {
+                    0xff
+                }
+            })
+            .collect();
+        camera.sensor.set_image(&image);
+
+        camera.set8(0x4000, 0x10);
+        camera.set8(0xa002, 0x10);
+        camera.set8(0xa003, 0x00);
+        for i in 0..16 {
+            camera.set8(0xa006 + i * 3, 0x40);
+            camera.set8(0xa007 + i * 3, 0x80);
+            camera.set8(0xa008 + i * 3, 0xc0);
+        }
+        camera.set8(0xa001, 0x80);
+        camera.set8(0xa000, 0x01);
+        assert_eq!(camera.get8(0xa000), 0x01);
+        camera.tick(camera.capture_cycles() - 4);
+        assert_eq!(camera.get8(0xa000), 0x01);
+        camera.tick(4);
+        assert_eq!(camera.get8(0xa000), 0x00);
+
+        // The first tile is black, the last tile of the first row white.
+        camera.set8(0x4000, 0x00);
+        assert_eq!(camera.get16(0xa100), 0xffff);
+        assert_eq!(camera.get16(0xa100 + 15 * 16), 0x0000);
+    }
+}
@@ -1,22 +1,54 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    gpu::Gpu,
+    apu::Apu,
+    gpu::{Gpu, Hdma, HdmaMode},
+    infrared::{Infrared, Rp},
     interrupt::Interrupt,
-    mbc::{Cartridge, CartridgeHeader, NoMBC, MBC1},
+    joypad::Joypad,
+    mbc::{Accessory, Cartridge, Mbc},
+    serial::Serial,
+    timer::Timer,
+    Term,
 };
 
-/// Unified memory IO interface 
-/// 
+/// Unified memory IO interface
+///
 /// Should it be all MemoryIO trait object rather than a `Rc<RefCell<>>`?
 pub struct Memory {
-    cartridge: Box<dyn MemoryIO>,
+    term: Term,
+    cartridge: Box<dyn Mbc>,
     gpu: Rc<RefCell<Gpu>>,
-    wram: [u8; 0x2000],
-    echo_ram: [u8; 0x1dff],
-    io_registers: [u8; 0x7f],
-    hram: [u8; 0x7e],
-    interrupt: Interrupt,
+    joypad: Rc<RefCell<Joypad>>,
+    apu: Rc<RefCell<Apu>>,
+    serial: Rc<RefCell<Serial>>,
+    hdma: Hdma,
+    timer: Timer,
+    /// Shared with the cartridge, which may have its own LED and sensor.
+    infrared: Rc<RefCell<Infrared>>,
+    rp: Rp,
+    /// 8 banks of 4 KiB on the CGB, the DMG only uses the first two.
+    wram: [u8; 0x8000],
+    /// The bank mapped at 0xd000-0xdfff, selected by SVBK (CGB only).
+    wram_bank: usize,
+    hram: [u8; 0x7f],
+    interrupt: Rc<RefCell<Interrupt>>,
+    speed: Speed,
+    /// KEY1 bit 0: the speed switch is performed by the next STOP instruction.
+    speed_switch_armed: bool,
+    /// Last value written to DMA (0xff46), the source page.
+    dma: u8,
+    /// Bytes copied by the running OAM DMA, `None` when there is no transfer.
+    dma_progress: Option<u16>,
+    /// CPU clock cycles during which the CPU is halted by HDMA/GDMA, added to the next tick.
+    hdma_stall: u32,
+}
+
+/// CPU speed (CGB only). In double speed mode the CPU and the timer run twice as fast, while the GPU keeps its pace.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Speed {
+    Normal = 0x01,
+    Double = 0x02,
 }
 
 pub trait MemoryIO {
@@ -31,121 +63,376 @@ pub trait MemoryIO {
 }
 
 impl Memory {
-    pub fn new(header: CartridgeHeader, gpu: Rc<RefCell<Gpu>>) -> Self {
+    pub fn new(
+        term: Term,
+        cartridge: Cartridge,
+        gpu: Rc<RefCell<Gpu>>,
+        joypad: Rc<RefCell<Joypad>>,
+        apu: Rc<RefCell<Apu>>,
+        serial: Rc<RefCell<Serial>>,
+        interrupt: Rc<RefCell<Interrupt>>,
+    ) -> Self {
+        let infrared = Rc::new(RefCell::new(Infrared::new()));
+        let mut cartridge = cartridge.into_mbc();
+        if let Some(Accessory::Infrared { port, .. }) = cartridge.accessory() {
+            port.connect(infrared.clone());
+        }
         Self {
-            cartridge: match header.cartridge_type() {
-                0 => Box::new(NoMBC::new()),
-                0x01 | 0x02 | 0x03 => Box::new(MBC1::new(header)),
-                _ => Box::new(NoMBC::new()),
-            },
+            term,
+            cartridge,
             gpu,
-            wram: [0; 0x2000],
-            echo_ram: [0; 0x1dff],
-            io_registers: [0; 0x7f],
-            hram: [0; 0x7e],
-            interrupt: Interrupt::new(),
+            joypad,
+            apu,
+            serial,
+            hdma: Hdma::new(),
+            timer: Timer::new(interrupt.clone()),
+            rp: Rp::new(infrared.clone()),
+            infrared,
+            wram: [0; 0x8000],
+            wram_bank: 0x01,
+            hram: [0; 0x7f],
+            interrupt,
+            speed: Speed::Normal,
+            speed_switch_armed: false,
+            dma: 0xff,
+            dma_progress: None,
+            hdma_stall: 0,
         }
     }
-}
 
-impl MemoryIO for Memory {
-    fn get8(&self, address: u16) -> u8 {
+    pub fn infrared(&self) -> Rc<RefCell<Infrared>> {
+        self.infrared.clone()
+    }
+
+    /// The mapper, e.g. to read the memory kept by the battery.
+    pub fn cartridge(&self) -> &dyn Mbc {
+        self.cartridge.as_ref()
+    }
+
+    /// The mapper, for the hardware of the cartridge which the host interacts with.
+    pub fn cartridge_mut(&mut self) -> &mut dyn Mbc {
+        self.cartridge.as_mut()
+    }
+
+    /// Advance the peripherals on the bus by `cycles` CPU clock cycles.
+    ///
+    /// Returns the elapsed time in normal speed clock cycles, which is what the GPU sees.
+    pub fn tick(&mut self, cycles: u32) -> u32 {
+        // The CPU doesn't run during HDMA/GDMA but everything else does.
+        let cycles = cycles + std::mem::take(&mut self.hdma_stall);
+        // The timer, the serial port and the OAM DMA run at the CPU speed.
+        self.tick_dma(cycles);
+        let div = self.timer.get8(0xff04);
+        self.timer.tick(cycles);
+        self.serial.borrow_mut().tick(cycles);
+        self.detect_div_apu(div);
+        let cycles = cycles / self.speed as u32;
+        self.gpu.borrow_mut().tick(cycles);
+        self.cartridge.tick(cycles);
+        let hblank = self.gpu.borrow_mut().take_hblank();
+        if hblank && self.hdma.active && self.hdma.mode == HdmaMode::Hdma {
+            self.hdma_block();
+        }
+        self.apu.borrow_mut().tick(cycles);
+        cycles
+    }
+
+    /// Copy one block of 0x10 bytes for HDMA/GDMA, to the VRAM bank selected by VBK. Each block halts the CPU for 8
+    /// M-cycles, which are twice as many clock cycles in double speed mode.
+    fn hdma_block(&mut self) {
+        for i in 0..0x10 {
+            let byte = self.read(self.hdma.source.wrapping_add(i));
+            self.gpu.borrow_mut().set8(
+                0x8000 | (self.hdma.destination.wrapping_add(i) & 0x1fff),
+                byte,
+            );
+        }
+        self.hdma.source = self.hdma.source.wrapping_add(0x10);
+        self.hdma.destination = 0x8000 | (self.hdma.destination.wrapping_add(0x10) & 0x1ff0);
+        // FF55 reads 0xff once the transfer is done.
+        if self.hdma.remain == 0 {
+            self.hdma.active = false;
+            self.hdma.remain = 0x7f;
+        } else {
+            self.hdma.remain -= 1;
+        }
+        self.hdma_stall += 32 * self.speed as u32;
+    }
+
+    /// Writing FF55 starts a transfer, or cancels a running H-Blank DMA.
+    fn start_hdma(&mut self, n: u8) {
+        let running = self.hdma.active && self.hdma.mode == HdmaMode::Hdma;
+        self.hdma.set8(0xff55, n);
+        if running || !self.hdma.active {
+            return;
+        }
+        match self.hdma.mode {
+            // All at once.
+            HdmaMode::Gdma => {
+                while self.hdma.active {
+                    self.hdma_block();
+                }
+            }
+            // The first block is copied right away if the GPU is already in H-Blank.
+            HdmaMode::Hdma => {
+                if self.gpu.borrow().in_hblank() {
+                    self.hdma_block();
+                }
+            }
+        }
+    }
+
+    /// The frame sequencer of the APU is clocked by the falling edge of DIV bit 4, or bit 5 in double speed mode so
+    /// that it keeps running at 512 Hz. Writing to DIV can clock it as well.
+    fn detect_div_apu(&mut self, old: u8) {
+        let bit = match self.speed {
+            Speed::Normal => 0x10,
+            Speed::Double => 0x20,
+        };
+        if old & bit != 0 && self.timer.get8(0xff04) & bit == 0 {
+            self.apu.borrow_mut().step_frame_sequencer();
+        }
+    }
+
+    /// OAM DMA copies 160 bytes from XX00-XX9F to OAM (0xfe00-0xfe9f), one byte per M-cycle. The source is read
+    /// through the bus, so it can be ROM, VRAM, cartridge RAM or WRAM. 0xe000-0xffff reads WRAM, like the echo RAM.
+    fn tick_dma(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            let n = match self.dma_progress {
+                Some(n) => n,
+                None => return,
+            };
+            let mut source = (u16::from(self.dma) << 8) | n;
+            if source >= 0xe000 {
+                source -= 0x2000;
+            }
+            let byte = self.read(source);
+            self.gpu.borrow_mut().set8(0xfe00 | n, byte);
+            self.dma_progress = if n < 0x9f { Some(n + 1) } else { None };
+        }
+    }
+
+    /// While OAM DMA is running, the external bus and OAM are busy: the CPU can only reach HRAM and the I/O
+    /// registers, which is why the DMA routine of every game is copied to HRAM. Everything else reads 0xff.
+    fn dma_blocks(&self, address: u16) -> bool {
+        self.dma_progress.is_some() && address < 0xff00
+    }
+
+    /// Performed by the STOP instruction: toggle the CPU speed if it was requested through KEY1. Returns whether
+    /// the speed has been switched.
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_armed {
+            return false;
+        }
+        self.speed = match self.speed {
+            Speed::Normal => Speed::Double,
+            Speed::Double => Speed::Normal,
+        };
+        self.speed_switch_armed = false;
+        true
+    }
+
+    /// Read from the bus, without the restrictions of OAM DMA.
+    fn read(&self, address: u16) -> u8 {
+        let cgb = self.term == Term::GBC;
         match address {
             0x0000..=0x7fff => self.cartridge.get8(address),
-            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.gpu.borrow().get8(address - 0x8000),
+            0x8000..=0x9fff => self.gpu.borrow().get8(address),
             0xa000..=0xbfff => self.cartridge.get8(address),
             0xc000..=0xcfff => self.wram[address as usize - 0xc000],
-            0xd000..=0xdfff => self.wram[address as usize - 0xc000],
-            0xe000..=0xfdff => self.echo_ram[address as usize - 0xe000],
+            0xd000..=0xdfff => self.wram[address as usize - 0xd000 + 0x1000 * self.wram_bank],
+            // Echo RAM mirrors 0xc000-0xddff.
+            0xe000..=0xefff => self.wram[address as usize - 0xe000],
+            0xf000..=0xfdff => self.wram[address as usize - 0xf000 + 0x1000 * self.wram_bank],
+            0xfe00..=0xfe9f => self.gpu.borrow().get8(address),
             0xfea0..=0xfeff => 0,
-            // 0xff00..=0xff7f => self.io_registers[address as usize - 0xff00],
+            0xff00 => self.joypad.borrow().get8(address),
+            0xff01 | 0xff02 => self.serial.borrow().get8(address),
+            0xff04..=0xff07 => self.timer.get8(address),
+            0xff0f => self.interrupt.borrow().get8(address),
+            0xff10..=0xff3f => self.apu.borrow().get8(address),
+            0xff46 => self.dma,
+            0xff40..=0xff4b => self.gpu.borrow().get8(address),
+            0xff4d if cgb => {
+                let speed = if self.speed == Speed::Double {
+                    0x80
+                } else {
+                    0x00
+                };
+                speed | 0x7e | u8::from(self.speed_switch_armed)
+            }
+            0xff4f | 0xff68..=0xff6c if cgb => self.gpu.borrow().get8(address),
+            // The source and destination are write-only and read as open bus.
+            0xff55 if cgb => self.hdma.get8(address),
+            0xff56 if cgb => self.rp.get8(address),
+            0xff70 if cgb => 0xf8 | self.wram_bank as u8,
+            0xff76 | 0xff77 if cgb => self.apu.borrow().pcm(address),
             0xff80..=0xfffe => self.hram[address as usize - 0xff80],
-            0xffff | 0xff0f => self.interrupt.get8(address),
-            _ => todo!("Other IO registers"),
+            0xffff => self.interrupt.borrow().get8(address),
+            // Unmapped registers read as open bus.
+            _ => 0xff,
         }
     }
 
-    fn set8(&mut self, address: u16, n: u8) {
+    fn write(&mut self, address: u16, n: u8) {
+        let cgb = self.term == Term::GBC;
         match address {
             0x0000..=0x7fff => self.cartridge.set8(address, n),
-            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.gpu.borrow_mut().set8(address - 0x8000, n),
+            0x8000..=0x9fff => self.gpu.borrow_mut().set8(address, n),
             0xa000..=0xbfff => self.cartridge.set8(address, n),
             0xc000..=0xcfff => self.wram[address as usize - 0xc000] = n,
-            0xd000..=0xdfff => self.wram[address as usize - 0xc000] = n,
-            0xe000..=0xfdff => self.echo_ram[address as usize - 0xe000] = n,
+            0xd000..=0xdfff => self.wram[address as usize - 0xd000 + 0x1000 * self.wram_bank] = n,
+            0xe000..=0xefff => self.wram[address as usize - 0xe000] = n,
+            0xf000..=0xfdff => self.wram[address as usize - 0xf000 + 0x1000 * self.wram_bank] = n,
+            0xfe00..=0xfe9f => self.gpu.borrow_mut().set8(address, n),
             0xfea0..=0xfeff => (),
-            // 0xff00..=0xff7f => self.io_registers[address as usize - 0xff00] = n,
+            0xff00 => self.joypad.borrow_mut().set8(address, n),
+            0xff01 | 0xff02 => self.serial.borrow_mut().set8(address, n),
+            0xff04..=0xff07 => {
+                let div = self.timer.get8(0xff04);
+                self.timer.set8(address, n);
+                self.detect_div_apu(div);
+            }
+            0xff0f => self.interrupt.borrow_mut().set8(address, n),
+            0xff10..=0xff3f => self.apu.borrow_mut().set8(address, n),
+            // Starts a new transfer, even if one is running.
+            0xff46 => {
+                self.dma = n;
+                self.dma_progress = Some(0);
+            }
+            0xff40..=0xff4b => self.gpu.borrow_mut().set8(address, n),
+            0xff4d if cgb => self.speed_switch_armed = n & 0x01 != 0,
+            0xff4f | 0xff68..=0xff6c if cgb => self.gpu.borrow_mut().set8(address, n),
+            0xff51..=0xff54 if cgb => self.hdma.set8(address, n),
+            0xff55 if cgb => self.start_hdma(n),
+            0xff56 if cgb => self.rp.set8(address, n),
+            // Writing 0 selects bank 1 as well.
+            0xff70 if cgb => self.wram_bank = usize::from(n & 0x07).max(1),
             0xff80..=0xfffe => self.hram[address as usize - 0xff80] = n,
-            0xffff | 0xff0f => self.interrupt.set8(address, n),
-            _ => todo!("Other IO registers"),
+            0xffff => self.interrupt.borrow_mut().set8(address, n),
+            _ => (),
         }
     }
+}
 
-    fn get16(&self, address: u16) -> u16 {
-        // (u16::from(self.mem[address as usize + 1]) << 8) | u16::from(self.mem[address as usize])
-        match address {
-            0x0000..=0x7fff => self.cartridge.get16(address),
-            _ => unimplemented!("These addresses are not for getting a 2-byte data")
-            // 0x8000..=0x9fff => unsafe {
-            //     *(self.vram.as_ptr().offset(address as isize - 0x8000) as *const u16)
-            // },
-            // 0xa000..=0xbfff => self.cartridge.get16(address),
-            // 0xc000..=0xcfff => unsafe {
-            //     *(self.wram.as_ptr().offset(address as isize - 0xc000) as *const u16)
-            // },
-            // 0xd000..=0xdfff => unsafe {
-            //     *(self.wram.as_ptr().offset(address as isize - 0xc000) as *const u16)
-            // },
-            // 0xe000..=0xfdff => unsafe {
-            //     *(self.echo_ram.as_ptr().offset(address as isize - 0xe000) as *const u16)
-            // },
-            // 0xfe00..=0xfe9f => unsafe {
-            //     *(self.oam.as_ptr().offset(address as isize - 0xfe00) as *const u16)
-            // },
-            // 0xfea0..=0xfeff => 0,
-            // 0xff00..=0xff7f => unsafe {
-            //     *(self.io_registers.as_ptr().offset(address as isize - 0xff00) as *const u16)
-            // },
-            // 0xff80..=0xfffe => unsafe {
-            //     *(self.hram.as_ptr().offset(address as isize - 0xff80) as *const u16)
-            // },
-            // 0xffff => 0,
+impl MemoryIO for Memory {
+    fn get8(&self, address: u16) -> u8 {
+        if self.dma_blocks(address) {
+            return 0xff;
+        }
+        self.read(address)
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        if !self.dma_blocks(address) {
+            self.write(address, n)
         }
     }
 
+    fn get16(&self, address: u16) -> u16 {
+        // Little endian: the low byte is stored at the lower address.
+        u16::from(self.get8(address)) | (u16::from(self.get8(address.wrapping_add(1))) << 8)
+    }
+
     fn set16(&mut self, address: u16, n: u16) {
-        // self.mem[address as usize] = (n >> 8) as u8;
-        // self.mem[address as usize + 1] = (n & 0x00ff) as u8;
-        match address {
-            0x0000..=0x7fff => self.cartridge.set16(address, n),
-            _ => unimplemented!("These addresses are not for setting a 2-byte data")
-            // 0x8000..=0x9fff => unsafe {
-            //     *(self.vram.as_mut_ptr().offset(address as isize - 0x8000) as *mut u16) = n;
-            // },
-            // 0xa000..=0xbfff => self.cartridge.set16(address, n),
-            // 0xc000..=0xcfff => unsafe {
-            //     *(self.wram.as_mut_ptr().offset(address as isize - 0xc000) as *mut u16) = n;
-            // },
-            // 0xd000..=0xdfff => unsafe {
-            //     *(self.wram.as_mut_ptr().offset(address as isize - 0xc000) as *mut u16) = n;
-            // },
-            // 0xe000..=0xfdff => unsafe {
-            //     *(self.echo_ram.as_mut_ptr().offset(address as isize - 0xe000) as *mut u16) = n;
-            // },
-            // 0xfe00..=0xfe9f => unsafe {
-            //     *(self.oam.as_mut_ptr().offset(address as isize - 0xfe00) as *mut u16) = n;
-            // },
-            // 0xfea0..=0xfeff => (),
-            // 0xff00..=0xff7f => unsafe {
-            //     *(self
-            //         .io_registers
-            //         .as_mut_ptr()
-            //         .offset(address as isize - 0xff00) as *mut u16) = n;
-            // },
-            // 0xff80..=0xfffe => unsafe {
-            //     *(self.hram.as_mut_ptr().offset(address as isize - 0xff80) as *mut u16) = n;
-            // },
-            // 0xffff => (),
-        };
+        self.set8(address, n as u8);
+        self.set8(address.wrapping_add(1), (n >> 8) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::DEFAULT_SAMPLE_RATE;
+
+    /// A bus with an empty cartridge.
+    fn memory(term: Term) -> Memory {
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let gpu = Rc::new(RefCell::new(Gpu::new(term, interrupt.clone())));
+        let joypad = Rc::new(RefCell::new(Joypad::new(interrupt.clone())));
+        let apu = Rc::new(RefCell::new(Apu::new(DEFAULT_SAMPLE_RATE)));
+        let serial = Rc::new(RefCell::new(Serial::new(term, interrupt.clone())));
+        Memory::new(
+            term,
+            Cartridge::try_from(vec![0; 0x8000]).unwrap(),
+            gpu,
+            joypad,
+            apu,
+            serial,
+            interrupt,
+        )
+    }
+
+    #[test]
+    fn test_io_registers() {
+        let mut memory = memory(Term::GB);
+        for address in 0xff00..=0xff7f {
+            memory.get8(address);
+        }
+        // Unmapped registers read as open bus.
+        assert_eq!(memory.get8(0xff7f), 0xff);
+        // CGB registers are not there on a DMG.
+        assert_eq!(memory.get8(0xff70), 0xff);
+
+        memory.set8(0xff42, 0x12);
+        assert_eq!(memory.get8(0xff42), 0x12);
+        memory.set8(0xc123, 0x34);
+        assert_eq!(memory.get8(0xe123), 0x34);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut memory = memory(Term::GB);
+        for i in 0..0xa0 {
+            memory.set8(0xc100 + i, i as u8);
+        }
+        memory.set8(0xff46, 0xc1);
+        assert_eq!(memory.get8(0xff46), 0xc1);
+        // Only HRAM and the registers can be reached during the transfer.
+        assert_eq!(memory.get8(0xc105), 0xff);
+        memory.set8(0xff80, 0x12);
+        assert_eq!(memory.get8(0xff80), 0x12);
+
+        memory.tick(159 * 4);
+        assert_eq!(memory.get8(0xfe00), 0xff);
+        memory.tick(4);
+        for i in 0..0xa0 {
+            assert_eq!(memory.get8(0xfe00 + i), i as u8);
+        }
+    }
+
+    #[test]
+    fn test_hdma() {
+        let mut memory = memory(Term::GBC);
+        for i in 0..0x40 {
+            memory.set8(0xc000 + i, i as u8);
+        }
+        memory.set8(0xff51, 0xc0);
+        memory.set8(0xff52, 0x00);
+        memory.set8(0xff53, 0x00);
+        memory.set8(0xff54, 0x00);
+        for address in 0xff51..=0xff54 {
+            assert_eq!(memory.get8(address), 0xff);
+        }
+
+        // GDMA: 2 blocks at once, then the CPU is halted for 16 M-cycles.
+        memory.set8(0xff55, 0x01);
+        assert_eq!(memory.get8(0xff55), 0xff);
+        assert_eq!(memory.get8(0x801f), 0x1f);
+        assert_eq!(memory.tick(4), 4 + 64);
+
+        // H-Blank DMA with the LCD on: one block per H-Blank.
+        memory.set8(0xff40, 0x80);
+        memory.set8(0xff55, 0x81);
+        assert_eq!(memory.get8(0xff55), 0x01);
+        assert_eq!(memory.get8(0x8020), 0x00);
+        memory.tick(252);
+        assert_eq!(memory.get8(0xff55), 0x00);
+        assert_eq!(memory.get8(0x802f), 0x2f);
+        // Cancelled before the next H-Blank.
+        memory.set8(0xff55, 0x00);
+        assert_eq!(memory.get8(0xff55), 0x80);
+        memory.tick(456);
+        assert_eq!(memory.get8(0x8030), 0x00);
     }
 }
@@ -0,0 +1,132 @@
+use std::time::Instant;
+
+use gb_emulator::gameboy::{GameBoy, FRAME_RATE};
+
+use crate::audio::Audio;
+
+/// Audio latency the pacer aims for, in seconds.
+const TARGET_LATENCY: f64 = 0.05;
+/// Frames are skipped while the queue holds more than this, in seconds, so that the latency stays bounded.
+const MAX_LATENCY: f64 = 0.15;
+/// Largest adjustment of the sample rate for dynamic rate control. 0.5% is not audible as a pitch change.
+const MAX_RATE_DELTA: f64 = 0.005;
+/// Never run more frames than this per repaint, so that a stall doesn't turn into a burst of fast-forward.
+const MAX_FRAMES: u32 = 4;
+
+/// Decides how many frames to emulate on each repaint of the window.
+///
+/// With an audio device, the emulation is paced by the audio clock: a frame is due every 1/59.73 s of samples played
+/// by the device. Both clocks are never exactly the same, so the sample rate is slightly adjusted every frame depending
+/// on the fill level of the queue (dynamic rate control): lower when it is above the target latency, higher below it.
+/// The queue neither drains (crackles) nor grows (drift). It is only refilled at once after running dry, e.g. on
+/// start, and frames are skipped beyond `MAX_LATENCY`.
+///
+/// Without an audio device, frames are run at 59.73 Hz according to the system timer, and the samples are dropped.
+pub enum Pacer {
+    Audio {
+        audio: Audio,
+        /// Frames played by the device at the last repaint.
+        played: u64,
+        /// Frames which are due but not run yet, fractional.
+        due: f64,
+    },
+    Timer {
+        last: Instant,
+        /// Frames which are due but not run yet, fractional.
+        due: f64,
+    },
+}
+
+impl Pacer {
+    /// Use the default audio device if there is one, the system timer otherwise.
+    pub fn new() -> Self {
+        match Audio::open() {
+            Some(audio) => Pacer::Audio {
+                played: audio.played(),
+                audio,
+                due: 0.0,
+            },
+            None => Pacer::Timer {
+                last: Instant::now(),
+                due: 0.0,
+            },
+        }
+    }
+
+    /// Run the frames that are due, and queue their audio. `before_frame` is called before each one, e.g. to feed the
+    /// accessories.
+    pub fn run(&mut self, gameboy: &mut GameBoy, mut before_frame: impl FnMut(&mut GameBoy)) {
+        match self {
+            Pacer::Audio { audio, played, due } => {
+                let rate = f64::from(audio.sample_rate());
+                let target = (rate * TARGET_LATENCY) as usize;
+                let now = audio.played();
+                *due += (now - *played) as f64 * FRAME_RATE / rate;
+                *played = now;
+                // Ran dry: refill up to the target at once.
+                if audio.queued() < target / 2 {
+                    *due = due.max((target - audio.queued()) as f64 * FRAME_RATE / rate);
+                }
+                *due = due.min(f64::from(MAX_FRAMES));
+                while *due >= 1.0 {
+                    *due -= 1.0;
+                    let queued = audio.queued();
+                    if queued as f64 >= rate * MAX_LATENCY {
+                        continue;
+                    }
+                    gameboy.set_sample_rate(adjusted_rate(audio.sample_rate(), queued, target));
+                    before_frame(gameboy);
+                    gameboy.run_frame();
+                    audio.push(&gameboy.take_samples());
+                }
+            }
+            Pacer::Timer { last, due } => {
+                let now = Instant::now();
+                *due += now.duration_since(*last).as_secs_f64() * FRAME_RATE;
+                *last = now;
+                if *due > f64::from(MAX_FRAMES) {
+                    *due = f64::from(MAX_FRAMES);
+                }
+                while *due >= 1.0 {
+                    *due -= 1.0;
+                    before_frame(gameboy);
+                    gameboy.run_frame();
+                    gameboy.take_samples();
+                }
+            }
+        }
+    }
+
+    /// Forget the time spent while the emulation was not running, e.g. paused or loading a ROM.
+    pub fn resync(&mut self) {
+        match self {
+            Pacer::Audio { audio, played, due } => {
+                *played = audio.played();
+                *due = 0.0;
+            }
+            Pacer::Timer { last, due } => {
+                *last = Instant::now();
+                *due = 0.0;
+            }
+        }
+    }
+}
+
+/// The rate to generate samples at: higher than the device rate when the queue is below the target, lower above it.
+fn adjusted_rate(device_rate: u32, queued: usize, target: usize) -> u32 {
+    let error = (target as f64 - queued as f64) / target as f64;
+    let ratio = 1.0 + MAX_RATE_DELTA * error.clamp(-1.0, 1.0);
+    (f64::from(device_rate) * ratio).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjusted_rate() {
+        assert_eq!(adjusted_rate(48_000, 1200, 2400), 48_120);
+        assert_eq!(adjusted_rate(48_000, 2400, 2400), 48_000);
+        assert_eq!(adjusted_rate(48_000, 9600, 2400), 47_760);
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, BufWriter},
+    path::PathBuf,
+    rc::Rc,
+};
+
+use crate::serial::SerialSink;
+
+/// Width of the paper, in pixels: 20 tiles.
+pub const PAPER_W: usize = 160;
+/// One packet of image data is 2 rows of 20 tiles.
+const BAND_SIZE: usize = 0x280;
+/// The printer holds up to 9 packets, a full screen.
+const BUFFER_SIZE: usize = BAND_SIZE * 9;
+/// Paper fed per unit of margin, in pixels.
+const MARGIN_UNIT: usize = 16;
+/// Shades of the thermal paper, from white to black.
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0f;
+
+/// Answer to the first byte after the checksum, the printer says hello.
+const DEVICE_ID: u8 = 0x81;
+
+bitflags::bitflags! {
+    struct Status: u8 {
+        const CHECKSUM_ERROR = 0x01;
+        const PRINTING = 0x02;
+        const IMAGE_DATA_FULL = 0x04;
+        const UNPROCESSED_DATA = 0x08;
+        const PACKET_ERROR = 0x10;
+    }
+}
+
+/// Position in the packet being received.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    DeviceId,
+    Status,
+}
+
+/// Game Boy Printer, plugged into the link port.
+///
+/// Every packet is sent by the Game Boy with the internal clock:
+///
+/// | 0x88 0x33 | Command | Compression | Length (LE) | Data | Checksum (LE) | 0x00 | 0x00 |
+///
+/// The checksum is the 16-bit sum of the bytes from the command to the end of the data. The printer answers 0x00 to
+/// every byte, except the two last ones: its device ID (0x81), then its status.
+///
+/// The image data is tiles in the VRAM format, 20 per row, optionally compressed with RLE. A print command gives the
+/// palette and the paper fed before and after the image. The paper keeps scrolling across print commands without a
+/// margin after them, and is cut into a new PNG file in `directory` once there is one. What is left on the paper is
+/// only saved by `flush`.
+///
+/// The printer is shared: keep a clone to check `take_error` and `flush` it after handing one to
+/// `GameBoy::set_serial_sink`.
+#[derive(Clone)]
+pub struct Printer(Rc<RefCell<Mechanism>>);
+
+impl Printer {
+    pub fn new(directory: PathBuf) -> Self {
+        Self(Rc::new(RefCell::new(Mechanism::new(directory))))
+    }
+
+    /// Cut the paper, saving what is printed on it.
+    pub fn flush(&self) -> io::Result<()> {
+        self.0.borrow_mut().cut()
+    }
+
+    /// The last error saving a printout, if any since the last call.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.0.borrow_mut().error.take()
+    }
+}
+
+struct Mechanism {
+    directory: PathBuf,
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    checksum: u16,
+    packet: Vec<u8>,
+    /// Received image data, decompressed.
+    buffer: Vec<u8>,
+    status: Status,
+    /// Status inquiries left until the current print is finished.
+    busy: u8,
+    /// Printed pixels, shades of gray, `PAPER_W` wide.
+    paper: Vec<u8>,
+    /// Number of PNG files written so far.
+    printouts: usize,
+    /// Saving the last printout failed.
+    error: Option<io::Error>,
+}
+
+impl Mechanism {
+    fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            checksum: 0,
+            packet: Vec::new(),
+            buffer: Vec::new(),
+            status: Status::empty(),
+            busy: 0,
+            paper: Vec::new(),
+            printouts: 0,
+            error: None,
+        }
+    }
+
+    /// Receive a byte and return the answer of the printer.
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut answer = 0x00;
+        self.state = match self.state {
+            State::Magic1 if byte == 0x88 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if byte == 0x33 => State::Command,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = byte;
+                self.checksum = u16::from(byte);
+                self.packet.clear();
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(u16::from(byte));
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = u16::from(byte);
+                self.checksum = self.checksum.wrapping_add(u16::from(byte));
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= u16::from(byte) << 8;
+                self.checksum = self.checksum.wrapping_add(u16::from(byte));
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(u16::from(byte));
+                if self.packet.len() == self.length as usize {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.checksum ^= u16::from(byte);
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.checksum ^= u16::from(byte) << 8;
+                State::DeviceId
+            }
+            State::DeviceId => {
+                answer = DEVICE_ID;
+                State::Status
+            }
+            State::Status => {
+                self.execute();
+                self.update_status();
+                answer = self.status.bits();
+                State::Magic1
+            }
+        };
+        answer
+    }
+
+    /// Run the command of the packet which has just been received.
+    fn execute(&mut self) {
+        // The checksum has been XORed with the received one.
+        if self.checksum != 0 {
+            self.status.insert(Status::CHECKSUM_ERROR);
+            return;
+        }
+        self.status.remove(Status::CHECKSUM_ERROR);
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.busy = 0;
+                self.status = Status::empty();
+            }
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.packet)
+                } else {
+                    std::mem::take(&mut self.packet)
+                };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+            }
+            PRINT => {
+                if let [_, margins, palette, _] = self.packet[..] {
+                    self.print(margins, palette);
+                    // Reported for a few inquiries, the program waits for it to go away.
+                    self.busy = 2;
+                } else {
+                    self.status.insert(Status::PACKET_ERROR);
+                }
+            }
+            STATUS => (),
+            _ => self.status.insert(Status::PACKET_ERROR),
+        }
+    }
+
+    fn update_status(&mut self) {
+        self.status.set(Status::PRINTING, self.busy > 0);
+        self.busy = self.busy.saturating_sub(1);
+        self.status
+            .set(Status::IMAGE_DATA_FULL, self.buffer.len() >= BUFFER_SIZE);
+        self.status
+            .set(Status::UNPROCESSED_DATA, !self.buffer.is_empty());
+    }
+
+    /// Print the buffer. The upper nibble of `margins` is the paper fed before the image and the lower one after it.
+    fn print(&mut self, margins: u8, palette: u8) {
+        self.feed(usize::from(margins >> 4) * MARGIN_UNIT);
+        let rows = self.buffer.len() / (PAPER_W / 8 * 16) * 8;
+        for y in 0..rows {
+            for x in 0..PAPER_W {
+                // Tile number then row in the tile, 2 bytes per row of 8 pixels.
+                let tile = (y / 8) * (PAPER_W / 8) + x / 8;
+                let offset = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                let color = ((self.buffer[offset] >> bit) & 0x01)
+                    | (((self.buffer[offset + 1] >> bit) & 0x01) << 1);
+                let shade = (palette >> (color * 2)) & 0x03;
+                self.paper.push(SHADES[shade as usize]);
+            }
+        }
+        self.buffer.clear();
+        let after = usize::from(margins & 0x0f) * MARGIN_UNIT;
+        if after > 0 {
+            self.feed(after);
+            if let Err(e) = self.cut() {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Feed blank paper.
+    fn feed(&mut self, rows: usize) {
+        // Nothing to feed before the first image.
+        if !self.paper.is_empty() {
+            self.paper
+                .resize(self.paper.len() + rows * PAPER_W, SHADES[0]);
+        }
+    }
+
+    /// Save the printout and tear it off. It stays on the paper if it can't be saved, for the next try.
+    fn cut(&mut self) -> io::Result<()> {
+        if self.paper.is_empty() {
+            return Ok(());
+        }
+        let path = self
+            .directory
+            .join(format!("printout-{:03}.png", self.printouts + 1));
+        save(&path, &self.paper)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        self.paper.clear();
+        self.printouts += 1;
+        Ok(())
+    }
+}
+
+impl SerialSink for Printer {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.0.borrow_mut().receive(byte)
+    }
+}
+
+/// RLE: a control byte with bit 7 set is followed by one byte repeated (control & 0x7f) + 2 times, otherwise by
+/// control + 1 literal bytes.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            if let Some(byte) = data.get(i) {
+                out.resize(out.len() + usize::from(control & 0x7f) + 2, *byte);
+            }
+            i += 1;
+        } else {
+            let end = (i + usize::from(control) + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+fn save(path: &PathBuf, paper: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        PAPER_W as u32,
+        (paper.len() / PAPER_W) as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(paper)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a whole packet, returns the answers to the two last bytes.
+    fn send(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> (u8, u8) {
+        let len = data.len() as u16;
+        let mut packet = vec![
+            0x88,
+            0x33,
+            command,
+            compression,
+            len as u8,
+            (len >> 8) as u8,
+        ];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, b| sum.wrapping_add(u16::from(*b)));
+        packet.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8, 0x00, 0x00]);
+        let answers: Vec<u8> = packet.iter().map(|b| printer.transfer(*b)).collect();
+        (answers[answers.len() - 2], answers[answers.len() - 1])
+    }
+
+    #[test]
+    fn test_print() {
+        let directory = std::env::temp_dir().join(format!("gb-printer-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut printer = Printer::new(directory.clone());
+
+        assert_eq!(send(&mut printer, INIT, 0, &[]), (DEVICE_ID, 0x00));
+        // One band of color 3, compressed: 0x280 bytes of 0xff.
+        let mut data = Vec::new();
+        for _ in 0..5 {
+            data.extend_from_slice(&[0xfe, 0xff]);
+        }
+        assert_eq!(decompress(&data).len(), BAND_SIZE);
+        assert_eq!(
+            decompress(&[0x01, 0x12, 0x34, 0x81, 0x56]),
+            [0x12, 0x34, 0x56, 0x56, 0x56]
+        );
+        assert_eq!(send(&mut printer, DATA, 1, &data), (DEVICE_ID, 0x08));
+        assert_eq!(send(&mut printer, DATA, 0, &[]).1, 0x08);
+
+        // A bad checksum is reported.
+        let (_, status) = {
+            let packet = [0x88, 0x33, STATUS, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+            let answers: Vec<u8> = packet.iter().map(|b| printer.transfer(*b)).collect();
+            (answers[8], answers[9])
+        };
+        assert_eq!(status & 0x01, 0x01);
+
+        // 1 sheet, no margin before, 1 after, palette 0xe4 (identity).
+        assert_eq!(
+            send(&mut printer, PRINT, 0, &[0x01, 0x01, 0xe4, 0x40]).1,
+            0x02
+        );
+        assert_eq!(send(&mut printer, STATUS, 0, &[]).1, 0x02);
+        assert_eq!(send(&mut printer, STATUS, 0, &[]).1, 0x00);
+
+        let path = directory.join("printout-001.png");
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.width, PAPER_W as u32);
+        assert_eq!(info.height, (16 + MARGIN_UNIT) as u32);
+        assert!(printer.take_error().is_none());
+
+        // Without a margin after it, the image stays on the paper until the printer is flushed.
+        send(&mut printer, DATA, 1, &data);
+        send(&mut printer, PRINT, 0, &[0x01, 0x00, 0xe4, 0x40]);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(printer.flush().is_err());
+        // Nothing is lost when it fails.
+        std::fs::create_dir_all(&directory).unwrap();
+        assert!(printer.flush().is_ok());
+        assert!(directory.join("printout-002.png").exists());
+        assert!(printer.flush().is_ok());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    interrupt::{IntFlag, Interrupt},
+    memory::MemoryIO,
+    Term,
+};
+
+/// The device at the other end of the link cable.
+pub trait SerialSink {
+    /// Exchange a byte: `byte` is shifted out of SB and the returned byte is shifted in.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Check whether the partner has started a transfer with its own clock, which is only possible for another Game
+    /// Boy. `byte` is SB if this side waits for a transfer with the external clock, `None` if no transfer is enabled.
+    ///
+    /// Returns the byte received if a transfer has been performed.
+    fn poll(&mut self, _byte: Option<u8>) -> Option<u8> {
+        None
+    }
+
+    /// Whether the partner is clocked bit by bit with `shift` instead of byte by byte with `transfer`, like another
+    /// Game Boy run in lockstep.
+    fn bitwise(&self) -> bool {
+        false
+    }
+
+    /// Exchange a bit on a clock edge driven by this side: `bit` is shifted out and the returned bit is shifted in.
+    fn shift(&mut self, _bit: bool) -> bool {
+        true
+    }
+}
+
+/// Collects the transmitted bytes as text, which is how test ROMs report their results. Nothing is sent back, like
+/// when no cable is plugged in.
+///
+/// The sink is shared: keep a clone to read the output after handing one to `GameBoy::set_serial_sink`.
+#[derive(Clone, Default)]
+pub struct StringSink(Rc<RefCell<String>>);
+
+impl StringSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn output(&self) -> String {
+        self.0.borrow().clone()
+    }
+
+    /// Cheap enough to be polled after every instruction, unlike `output`.
+    pub fn ends_with(&self, pattern: &str) -> bool {
+        self.0.borrow().ends_with(pattern)
+    }
+}
+
+impl SerialSink for StringSink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.0.borrow_mut().push(byte as char);
+        0xff
+    }
+}
+
+/// SB and SC
+///
+/// A transfer shifts the 8 bits of SB out, MSB first, while the bits of the partner are shifted in. With the internal
+/// clock the Game Boy drives the cable at 8192 Hz (262144 Hz with the CGB fast clock), so a byte takes 4096 clock
+/// cycles. The serial interrupt is requested once the 8 bits are done. With the external clock the partner drives
+/// the transfer: bit by bit for a partner in lockstep, see `SerialSink::bitwise`, otherwise at once when the partner
+/// reports it.
+///
+/// SC:
+/// - Bit 7 - Transfer enable   (1=Transfer requested or in progress)
+/// - Bit 1 - Clock speed       (0=Normal, 1=Fast) (CGB only)
+/// - Bit 0 - Shift clock       (0=External, 1=Internal)
+pub struct Serial {
+    term: Term,
+    sb: u8,
+    sc: u8,
+    /// The byte being shifted in, received from the sink when the transfer started.
+    incoming: u8,
+    /// Bits left in the current transfer, with either clock.
+    bits: u8,
+    /// Clock cycles until the next bit is shifted.
+    timer: u32,
+    /// Clock cycles until the sink is polled for a transfer from the partner.
+    poll_timer: u32,
+    sink: Option<Box<dyn SerialSink>>,
+    interrupt: Rc<RefCell<Interrupt>>,
+}
+
+impl Serial {
+    pub fn new(term: Term, interrupt: Rc<RefCell<Interrupt>>) -> Self {
+        Self {
+            term,
+            sb: 0,
+            sc: 0,
+            incoming: 0xff,
+            bits: 0,
+            timer: 0,
+            poll_timer: 0,
+            sink: None,
+            interrupt,
+        }
+    }
+
+    /// Plug a device into the link port, or unplug it with `None`.
+    pub fn set_sink(&mut self, sink: Option<Box<dyn SerialSink>>) {
+        self.sink = sink;
+    }
+
+    /// Clock cycles per bit with the internal clock.
+    fn period(&self) -> u32 {
+        if self.term == Term::GBC && self.sc & 0x02 != 0 {
+            16
+        } else {
+            512
+        }
+    }
+
+    /// 往前走若干个时钟周期（CPU速度）
+    pub fn tick(&mut self, cycles: u32) {
+        self.poll(cycles);
+        // Only the internal clock is driven by this side.
+        if self.bits == 0 || self.sc & 0x01 == 0 {
+            return;
+        }
+        let mut cycles = cycles;
+        while self.bits > 0 && cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            let bit = match self.sink.as_mut() {
+                Some(sink) if sink.bitwise() => u8::from(sink.shift(self.sb & 0x80 != 0)),
+                _ => self.incoming >> 7,
+            };
+            self.sb = (self.sb << 1) | bit;
+            self.incoming <<= 1;
+            self.bits -= 1;
+        }
+        if self.bits == 0 {
+            self.finish();
+        } else {
+            self.timer -= cycles;
+        }
+    }
+
+    /// Let the partner clock a transfer in. Polled once per bit period rather than every instruction, since it may
+    /// go through a socket.
+    fn poll(&mut self, cycles: u32) {
+        let sink = match self.sink.as_mut() {
+            Some(sink) => sink,
+            None => return,
+        };
+        if self.poll_timer > cycles {
+            self.poll_timer -= cycles;
+            return;
+        }
+        self.poll_timer = 512;
+        let waiting = self.sc & 0x81 == 0x80;
+        if let Some(byte) = sink.poll(if waiting { Some(self.sb) } else { None }) {
+            self.clock_in(byte);
+        }
+    }
+
+    /// A bit clocked by the partner. Returns the bit shifted out, or `None` if this side doesn't wait for a transfer
+    /// with the external clock, in which case nothing happens. The transfer is done after the eighth one.
+    pub fn shift_in(&mut self, bit: bool) -> Option<bool> {
+        if self.sc & 0x81 != 0x80 || self.bits == 0 {
+            return None;
+        }
+        let out = self.sb & 0x80 != 0;
+        self.sb = (self.sb << 1) | u8::from(bit);
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.finish();
+        }
+        Some(out)
+    }
+
+    /// A whole transfer clocked by the partner. Returns the byte shifted out, or `None` if this side doesn't wait for
+    /// a transfer with the external clock, in which case nothing happens.
+    pub fn clock_in(&mut self, byte: u8) -> Option<u8> {
+        if self.sc & 0x81 != 0x80 {
+            return None;
+        }
+        let out = self.sb;
+        self.sb = byte;
+        self.bits = 0;
+        self.finish();
+        Some(out)
+    }
+
+    fn finish(&mut self) {
+        self.sc &= 0x7f;
+        self.interrupt
+            .borrow_mut()
+            .request_interrupt(IntFlag::SERIAL);
+    }
+
+    fn start(&mut self) {
+        // Without a partner, the input line is pulled up and 0xff is received.
+        self.incoming = match self.sink.as_mut() {
+            Some(sink) if sink.bitwise() => 0xff,
+            Some(sink) => sink.transfer(self.sb),
+            None => 0xff,
+        };
+        self.bits = 8;
+        self.timer = self.period();
+    }
+}
+
+impl MemoryIO for Serial {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0xff01 => self.sb,
+            0xff02 if self.term == Term::GBC => 0x7c | self.sc,
+            0xff02 => 0x7e | self.sc,
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        match address {
+            0xff01 => self.sb = n,
+            0xff02 => {
+                self.sc = if self.term == Term::GBC {
+                    n & 0x83
+                } else {
+                    n & 0x81
+                };
+                if self.sc & 0x81 == 0x81 {
+                    self.start();
+                } else if self.sc & 0x80 != 0 {
+                    // Waiting for the clock of the partner.
+                    self.bits = 8;
+                } else {
+                    self.bits = 0;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get16(&self, _: u16) -> u16 {
+        unimplemented!("Serial doesn't support reading 2-byte data.")
+    }
+
+    fn set16(&mut self, _: u16, _: u16) {
+        unimplemented!("Serial doesn't support writing 2-byte data.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_without_partner() {
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let mut serial = Serial::new(Term::GB, interrupt.clone());
+        let sink = StringSink::new();
+        serial.set_sink(Some(Box::new(sink.clone())));
+
+        serial.set8(0xff01, b'P');
+        serial.set8(0xff02, 0x81);
+        assert_eq!(serial.get8(0xff02), 0xff);
+        serial.tick(4092);
+        assert_eq!(interrupt.borrow().request, 0);
+        assert_eq!(serial.get8(0xff02), 0xff);
+
+        serial.tick(4);
+        assert_eq!(serial.get8(0xff01), 0xff);
+        assert_eq!(serial.get8(0xff02), 0x7f);
+        assert_eq!(interrupt.borrow().request, IntFlag::SERIAL.bits());
+        assert_eq!(sink.output(), "P");
+    }
+}
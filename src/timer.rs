@@ -0,0 +1,150 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    interrupt::{IntFlag, Interrupt},
+    memory::MemoryIO,
+};
+
+/// DIV, TIMA, TMA and TAC.
+///
+/// DIV is the upper 8 bits of a 16-bit counter which is incremented every clock cycle. TIMA is not driven by its own
+/// clock: it is incremented on the falling edge of one bit of that counter (selected by TAC) ANDed with the enable
+/// bit of TAC. This is why writing to DIV, which resets the whole counter, or to TAC can increment TIMA.
+///
+/// - TAC 00: bit 9, 4096 Hz
+/// - TAC 01: bit 3, 262144 Hz
+/// - TAC 10: bit 5, 65536 Hz
+/// - TAC 11: bit 7, 16384 Hz
+pub struct Timer {
+    /// The internal counter, DIV is its upper 8 bits.
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMA overflowed during the last M-cycle. It reads 0 until it is reloaded from TMA, one M-cycle later, which is
+    /// also when the interrupt is requested.
+    overflow: bool,
+    interrupt: Rc<RefCell<Interrupt>>,
+}
+
+impl Timer {
+    pub fn new(interrupt: Rc<RefCell<Interrupt>>) -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            interrupt,
+        }
+    }
+
+    /// 往前走若干个时钟周期，以M-cycle（4个时钟周期）为单位
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            if self.overflow {
+                self.overflow = false;
+                self.tima = self.tma;
+                self.interrupt
+                    .borrow_mut()
+                    .request_interrupt(IntFlag::TIMER);
+            }
+            let signal = self.signal();
+            self.counter = self.counter.wrapping_add(4);
+            self.detect_falling_edge(signal);
+        }
+    }
+
+    /// The input of the TIMA falling edge detector.
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn detect_falling_edge(&mut self, old: bool) {
+        if old && !self.signal() {
+            let (tima, overflow) = self.tima.overflowing_add(1);
+            self.tima = tima;
+            self.overflow = overflow;
+        }
+    }
+}
+
+impl MemoryIO for Timer {
+    fn get8(&self, address: u16) -> u8 {
+        match address {
+            0xff04 => (self.counter >> 8) as u8,
+            0xff05 => self.tima,
+            0xff06 => self.tma,
+            // The upper 5 bits are unused.
+            0xff07 => 0xf8 | self.tac,
+            _ => 0xff,
+        }
+    }
+
+    fn set8(&mut self, address: u16, n: u8) {
+        let signal = self.signal();
+        match address {
+            0xff04 => self.counter = 0,
+            0xff05 => {
+                // Writing TIMA during the M-cycle after an overflow cancels the reload.
+                self.tima = n;
+                self.overflow = false;
+            }
+            0xff06 => self.tma = n,
+            0xff07 => self.tac = n & 0x07,
+            _ => (),
+        }
+        self.detect_falling_edge(signal);
+    }
+
+    fn get16(&self, _: u16) -> u16 {
+        unimplemented!("Timer doesn't support reading 2-byte data.")
+    }
+
+    fn set16(&mut self, _: u16, _: u16) {
+        unimplemented!("Timer doesn't support writing 2-byte data.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tima_overflow() {
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let mut timer = Timer::new(interrupt.clone());
+        timer.set8(0xff06, 0x42);
+        timer.set8(0xff05, 0xff);
+        timer.set8(0xff07, 0x05);
+
+        // 262144 Hz: TIMA is incremented every 16 clock cycles.
+        timer.tick(16);
+        assert_eq!(timer.get8(0xff05), 0x00);
+        assert_eq!(interrupt.borrow().request, 0);
+
+        // Reloaded one M-cycle later.
+        timer.tick(4);
+        assert_eq!(timer.get8(0xff05), 0x42);
+        assert_eq!(interrupt.borrow().request, IntFlag::TIMER.bits());
+    }
+
+    #[test]
+    fn test_div_write_falling_edge() {
+        let interrupt = Rc::new(RefCell::new(Interrupt::new()));
+        let mut timer = Timer::new(interrupt);
+        timer.set8(0xff07, 0x05);
+        // Bit 3 of the counter is now set.
+        timer.tick(8);
+        assert_eq!(timer.get8(0xff05), 0x00);
+        timer.set8(0xff04, 0x12);
+        assert_eq!(timer.get8(0xff04), 0x00);
+        assert_eq!(timer.get8(0xff05), 0x01);
+    }
+}